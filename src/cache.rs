@@ -0,0 +1,84 @@
+use crate::config::cache_home;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a "mod not found" answer is trusted, independently of the configured TTL
+const NEGATIVE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Portal answer for a mod, `body` is None if the mod wasn't found
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    fetched_at: u64,
+    body: Option<String>
+}
+
+pub enum Cached {
+    Found(String),
+    NotFound
+}
+
+/// On-disk cache of mod info responses of the portal
+pub struct Cache {
+    path: PathBuf,
+    ttl: Duration,
+    /// Entries fetched before this time are expired regardless of the TTL
+    fresh_since: u64
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0)
+}
+
+impl Cache {
+    pub fn new(ttl: Duration) -> Self {
+        Cache {
+            path: cache_home().join("mods"),
+            ttl,
+            fresh_since: 0
+        }
+    }
+
+    /// Treats the entries stored so far as expired, the ones fetched from now on are still used.
+    pub fn refreshed(mut self) -> Self {
+        self.fresh_since = now();
+        self
+    }
+
+    fn entry_path(&self, name: &str) -> PathBuf {
+        self.path.join(format!("{}.json", name))
+    }
+
+    /// Returns the cached answer for the mod unless it has expired.
+    pub fn get(&self, name: &str) -> Option<Cached> {
+        let entry: CacheEntry = serde_json::from_str(&read_to_string(self.entry_path(name)).ok()?).ok()?;
+
+        let ttl = match entry.body {
+            Some(_) => self.ttl,
+            None => self.ttl.min(NEGATIVE_TTL)
+        };
+
+        if now().saturating_sub(entry.fetched_at) >= ttl.as_secs() || entry.fetched_at < self.fresh_since {
+            return None;
+        }
+
+        Some(match entry.body {
+            Some(body) => Cached::Found(body),
+            None => Cached::NotFound
+        })
+    }
+
+    /// Stores the answer for the mod, errors are ignored since the cache is only an optimization.
+    pub fn put(&self, name: &str, body: Option<&str>) {
+        let entry = CacheEntry {
+            fetched_at: now(),
+            body: body.map(|x| x.to_string())
+        };
+
+        if let Ok(json) = serde_json::to_string(&entry) {
+            _ = create_dir_all(&self.path);
+            _ = write(self.entry_path(name), json);
+        }
+    }
+}
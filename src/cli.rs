@@ -1,34 +1,102 @@
 use crate::cli::Commands::Info;
-use crate::config::Config;
-use crate::downloader::Downloader;
-use crate::factorio_api::FactorioApi;
-use crate::instance::Instance;
-use crate::mod_info::Version;
-use crate::utils::{process_dependencies, Changes};
-use clap::{Parser, Subcommand};
-use colored::Colorize;
-use std::io::stdin;
-use std::mem::take;
-use std::path::PathBuf;
+use fmods::cache::Cache;
+use fmods::config;
+use fmods::config::{config_path, expand_home, Config, ConfirmMode, InstanceRegistry};
+use fmods::downloader::{read_archive_info, Downloader, Source};
+use fmods::factorio_api::{FactorioApi, ModSource};
+use fmods::history;
+use fmods::history::{Action, HistoryEntry, Since};
+use fmods::http;
+use fmods::instance;
+use fmods::instance::{InstalledMod, Instance, Manifest};
+use fmods::lock::{lock_path, InstanceLock};
+use fmods::mod_info::{minimum_factorio_version, parse_changelog, validate_mod_id, ChangelogEntry, Dependency, DependencyType, ModInfo, ModRelease, ModReleaseInfoJson, ModSummary, PartialVersion, ReleaseSelector, Version, VersionRange};
+use fmods::mod_list::{mod_list_path, ModList, ModListDiff};
+use fmods::pipeline;
+use fmods::pipeline::{Event, Mismatch, Observer, Phase};
+use fmods::utils;
+use fmods::utils::{compute_load_order, csv_row, dependencies_dot, dependency_tree, edit_distance, format_size, glob_match, is_glob, is_mod_game_content, parallel_map, parse_size, process_dependencies_of, required_by, unmet_requirements, Changes, InstallChange, ModsDiff, UpdateChange};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::{generate, Shell};
+use colored::{ColoredString, Colorize};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::fs::{create_dir_all, read_dir, remove_dir_all, OpenOptions};
+use std::io;
+use std::io::{stderr, stdin, stdout, IsTerminal, Write};
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
-struct Args {
+pub struct Args {
     #[arg(long)]
     ask: bool,
 
+    /// When to use colors in the output
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    pub color: ColorMode,
+
     #[arg(long)]
     no_ask: bool,
 
+    /// Confirm all operations without asking
+    #[arg(long, short)]
+    yes: bool,
+
+    /// Explain how releases are selected
+    #[arg(long, short)]
+    pub verbose: bool,
+
+    /// Always fetch mod info from the portal, ignoring cached answers
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Fetch mod info from the portal again, but keep caching it for the rest of the command
+    #[arg(long, conflicts_with = "no_cache")]
+    refresh: bool,
+
+    /// Seconds a request, including a whole download, may take, overrides `timeout` from the config
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// Download mods from mods.factorio.com instead of the mirror, needs credentials
+    #[arg(long)]
+    official_portal: bool,
+
+    /// Command run after mods were installed or updated, overrides `post_install_command` from the config
+    #[arg(long)]
+    post_hook: Option<String>,
+
+    /// Upper limit of parallel requests and downloads, overrides `jobs` from the config
+    #[arg(long)]
+    jobs: Option<usize>,
+
     #[arg(long)]
     instance: Option<String>,
 
+    /// Directory for the config, history and cache instead of the default ones, overrides `FMODS_HOME`
+    #[arg(long)]
+    pub home: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(ValueEnum, Clone, Copy, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Colors only if stdout is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
 #[derive(Subcommand, Eq, PartialEq)]
 enum Commands {
     /// Work with instances
@@ -37,20 +105,355 @@ enum Commands {
         command: InstancesCommands
     },
     /// Info about instance
-    Info,
+    Info {
+        /// Show every configured instance
+        #[arg(long)]
+        all_instances: bool,
+
+        /// Show an installed mod instead, with the Factorio version it requires
+        #[arg(long = "mod", conflicts_with = "all_instances")]
+        mod_name: Option<String>,
+    },
     /// List installed mods
-    List,
-    /// Download mod
+    List {
+        /// Only list the mods matching the pattern, `*` matches any chars and `?` a single char
+        pattern: Option<String>,
+
+        /// List the mods of every configured instance
+        #[arg(long)]
+        all_instances: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with = "all_instances")]
+        format: OutputFormat,
+    },
+    /// Download mods
     Download {
+        /// Mod ids, each optionally as `name@version`. A single id may be followed by the version to install,
+        /// `latest` selects the newest compatible release
+        #[arg(required_unless_present = "from_file")]
+        mods: Vec<String>,
+
+        /// Also install the mods listed in this file, one `name` or `name@version` per line, `-` reads stdin
+        #[arg(long)]
+        from_file: Option<PathBuf>,
+
+        /// Only install missing mods, never update installed ones
+        #[arg(long)]
+        only_missing: bool,
+
+        /// Number of the newest releases shown when selecting the version
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+
+        /// Install the release whose archive has this sha1, only for a single mod
+        #[arg(long, conflicts_with = "file_name")]
+        sha1: Option<String>,
+
+        /// Install the release with this archive name, e.g. `foo_1.0.0.zip`, only for a single mod
+        #[arg(long)]
+        file_name: Option<String>,
+
+        /// Remove other versions of the installed mods from the mods directory
+        #[arg(long)]
+        prune: bool,
+
+        /// Refuse to download more than this in total, e.g. `500MiB`
+        #[arg(long, value_parser = parse_size)]
+        max_download: Option<u64>,
+
+        /// Accept releases for any Factorio version with the same major, e.g. 2.1 mods on 2.0
+        #[arg(long)]
+        major_compat: bool,
+
+        /// Only install the dependencies of the given mods, e.g. of a mod developed locally
+        #[arg(long)]
+        deps_only: bool,
+
+        /// Only install the given mods without their dependencies, e.g. to test a mod in isolation
+        #[arg(long, conflicts_with = "deps_only")]
+        no_deps: bool,
+
+        /// Stop before resolving if the Factorio version of the instance couldn't be detected, instead of only warning
+        #[arg(long)]
+        instance_version_check: bool,
+
+        /// After installing, warn if the info.json dependencies of the installed mods differ from the ones the portal reported
+        #[arg(long)]
+        reconcile: bool,
+    },
+    /// Download a mod with all of its dependencies as zip files into a directory, without installing them
+    Bundle {
+        name: String,
+        /// Version to bundle, `latest` selects the newest compatible release
+        mod_version: Option<VersionRequest>,
+
+        /// Directory for the archives
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Show the resolved dependencies of a mod, no matter which mods are installed
+    Deps {
+        name: String,
+        /// Version to resolve, the newest compatible release by default
+        mod_version: Option<VersionRequest>,
+
+        /// Print a Graphviz DOT graph instead of the tree
+        #[arg(long)]
+        dot: bool,
+
+        /// Levels of dependencies shown below the mod
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+    /// Show the changes of the releases newer than the installed or the given version of a mod
+    Changelog {
         name: String,
-        mod_version: Option<Version>,
+        /// Show changes after this version instead of the installed one
+        from_version: Option<Version>,
+    },
+    /// Keep a mod within a version range like ">=1.0, <2.0", list the pins without arguments
+    Pin {
+        name: Option<String>,
+        #[arg(requires = "name")]
+        range: Option<VersionRange>,
+    },
+    /// Remove the pin of a mod
+    Unpin {
+        name: String,
+    },
+    /// Write the Factorio version and the installed mods to a manifest file
+    Manifest {
+        out: PathBuf,
+    },
+    /// Show what installing mods would change on the mods of a manifest, without an installed game
+    Plan {
+        manifest: PathBuf,
+        /// Mods as `name` or `name@version`
+        #[arg(required = true)]
+        mods: Vec<String>,
+    },
+    /// Install mods from a directory of zip files made by `bundle`, without network
+    InstallBundle {
+        dir: PathBuf,
+    },
+    /// Update installed mods to their newest compatible releases
+    Update {
+        /// Mods to update, all installed mods if none are given
+        names: Vec<String>,
+
+        /// Keep these mods at their current versions for this run
+        #[arg(long, value_delimiter = ',')]
+        exclude: Vec<String>,
+
+        /// Remove old versions of the updated mods from the mods directory
+        #[arg(long)]
+        prune: bool,
+
+        /// Also update mods disabled in mod-list.json, they are kept at their versions by default
+        #[arg(long)]
+        include_disabled: bool,
+
+        /// Accept releases for any Factorio version with the same major, e.g. 2.1 mods on 2.0
+        #[arg(long)]
+        major_compat: bool,
+
+        /// Only print the mods with newer releases in this format, nothing is installed
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
     /// Remove mod
     Remove {
+        /// Mod id or a pattern like `bob*` matching several installed mods
+        name: String,
+
+        /// Remove the mods even if other installed mods require them
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the installed mods which depend on an installed mod, with their version requirements
+    Dependents {
+        name: String,
+
+        /// Also list mods for which it's an optional dependency
+        #[arg(long)]
+        optional: bool,
+    },
+    /// Show the order in which the game loads installed mods
+    Order,
+    /// Install the exact mod versions of one instance into another
+    Sync {
+        source: String,
+        target: String,
+
+        /// Also remove the mods of the target which the source doesn't have
+        #[arg(long)]
+        remove_extra: bool,
+    },
+    /// Compare the mods of two instances
+    Diff {
+        first: String,
+        second: String,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Download installed mod again, without name repairs mods with broken files
+    Repair {
+        name: Option<String>,
+    },
+    /// Show paths used for the instance
+    Paths,
+    /// Print the version, paths, instances, settings and network status of fmods for a bug report
+    Selfcheck,
+    /// Summary of the instance: disk usage, disabled and outdated mods, categories
+    Stats,
+    /// Check which installed mods have releases for another Factorio version
+    Compat {
+        factorio_version: Version,
+    },
+    /// Plan updating all installed mods to another Factorio version
+    Migrate {
+        factorio_version: Version,
+
+        /// Perform the planned updates
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Search mods on the portal by id or title
+    Search {
+        query: String,
+
+        /// Only show mods with the given category or tag
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Only show mods with a release compatible with the instance
+        #[arg(long)]
+        compatible: bool,
+
+        /// Maximum number of results to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show mod details and its compatible releases
+    Show {
         name: String,
+
+        /// List all releases, including incompatible ones
+        #[arg(long)]
+        all_versions: bool,
+    },
+    /// Show performed installs, updates and removals, filtered by --instance if given
+    History {
+        /// Only show operations since a duration ago (`7d`, `24h`) or a UTC date (`2024-05-01`)
+        #[arg(long)]
+        since: Option<Since>,
+    },
+    /// Revert the last recorded operation on the instance
+    Undo,
+    /// Read or change settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands
+    },
+    /// Compare the installed mods with mod-list.json
+    Check,
+    /// Enable installed mods in mod-list.json
+    Enable {
+        /// Mod ids or patterns like `bob*`
+        #[arg(required = true)]
+        names: Vec<String>,
+
+        /// Only show the changes of mod-list.json without writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Disable installed mods in mod-list.json
+    Disable {
+        /// Mod ids or patterns like `bob*`
+        #[arg(required = true)]
+        names: Vec<String>,
+
+        /// Only show the changes of mod-list.json without writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print shell completion script
+    Completions {
+        shell: Shell,
+    },
+    /// Print values for dynamic shell completion
+    #[command(hide = true)]
+    CompleteValues {
+        kind: CompletionKind,
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Eq, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+/// Installed mod as listed by `list --format`
+#[derive(Serialize)]
+struct ListedMod {
+    name: String,
+    version: Version,
+    enabled: bool,
+    /// In bytes
+    size: u64
+}
+
+/// Newer release of an installed mod as printed by `update --format`
+#[derive(Serialize)]
+struct AvailableUpdate {
+    name: String,
+    current: Version,
+    latest: Version
+}
+
+#[derive(ValueEnum, Clone, Eq, PartialEq)]
+enum CompletionKind {
+    Instances,
+    Mods,
+}
+
+/// Version argument of a command, `latest` is reserved for the newest compatible release.
+#[derive(Clone, Eq, PartialEq)]
+enum VersionRequest {
+    Latest,
+    Exact(Version),
+}
+
+impl FromStr for VersionRequest {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("latest") {
+            return Ok(VersionRequest::Latest);
+        }
+
+        Ok(VersionRequest::Exact(Version::from_str(s)?))
     }
 }
 
+#[derive(Subcommand, Eq, PartialEq)]
+enum ConfigCommands {
+    /// Print the value of a setting
+    Get {
+        key: String
+    },
+    /// Change a setting
+    Set {
+        key: String,
+        value: String
+    },
+    /// Report keys of the config file which have no effect, like misspelled ones
+    Validate
+}
+
 #[derive(Subcommand, Eq, PartialEq)]
 enum InstancesCommands {
     /// Add new instance
@@ -62,31 +465,281 @@ enum InstancesCommands {
         replace: bool,
 
         #[arg(long)]
-        default: bool
+        default: bool,
+
+        /// The path is only a mods directory (e.g. of a headless server) used with this Factorio version
+        #[arg(long)]
+        factorio_version: Option<Version>,
+
+        /// Store the path without checking it, e.g. for a game which will be installed later
+        #[arg(long)]
+        no_validate: bool
     },
     /// Remove an instance
     Remove {
         name: String
     },
     /// List all instances
-    List,
+    List {
+        /// Show version and mods count of each instance
+        #[arg(long)]
+        verbose: bool
+    },
     /// Set default instance
     Default {
         name: String
     },
     /// Unset default
-    UnsetDefault
+    UnsetDefault,
+    /// Write the instances to a file for importing them on another machine
+    Export {
+        file: PathBuf
+    },
+    /// Add the instances from a file written by `instances export`
+    Import {
+        file: PathBuf,
+
+        /// Directory replacing the home directory in the exported paths
+        #[arg(long)]
+        prefix: Option<PathBuf>,
+
+        /// Replace instances with the same names
+        #[arg(long)]
+        replace: bool
+    }
+}
+
+fn save_config(config: &Config) -> Result<(), CliError> {
+    config.save().map_err(CliError::SaveConfig)
+}
+
+/// Failure of a command, `main` prints it and exits with a non-zero code
+#[derive(Debug)]
+pub enum CliError {
+    NoInstances,
+    NoInstanceSelected,
+    InstanceExists(String),
+    InstanceNotFound { name: String, closest: Option<String>, configured: Vec<String> },
+    OpenInstance { name: String, err: instance::Error },
+    Config(config::Error),
+    SaveConfig(Box<dyn std::error::Error>),
+    InstancesFile { path: PathBuf, err: Box<dyn std::error::Error> },
+    ManifestFile { path: PathBuf, err: Box<dyn std::error::Error> },
+    InvalidArguments(String),
+    InvalidModId { id: String, reason: String },
+    ModNotFound { id: String, reason: String },
+    NotInstalled(String),
+    StillRequired(usize),
+    NoSuitableRelease(String),
+    Fetch(ureq::Error),
+    Dependencies(utils::Error),
+    Download { id: String, err: Box<dyn std::error::Error + Send + Sync> },
+    Io { path: PathBuf, err: io::Error },
+    TooLarge { total: u64, max: u64 },
+    /// Another process is changing the mods of the instance
+    Locked(String),
+    /// Some mods failed, the others were processed and the failures reported
+    Failed(usize),
+    /// The Factorio version of the instance at the path is 0.0.0 or base is missing
+    UnknownFactorioVersion(PathBuf)
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::NoInstances => write!(f, "No instances are configured yet. Add your Factorio installation first:\n  {}",
+                                            "fmods instances add <name> <path to the game> --default".bright_yellow()),
+            CliError::NoInstanceSelected => f.write_str("No instance selected."),
+            CliError::InstanceExists(name) => write!(f, "The instance \"{}\" already exists, use --replace to replace it.", name),
+            CliError::InstanceNotFound { name, closest, configured } => {
+                write!(f, "A instance with the name \"{}\" was not found.", name)?;
+
+                if let Some(closest) = closest {
+                    write!(f, "\nDid you mean \"{}\"?", closest.bright_yellow())?;
+                }
+
+                if !configured.is_empty() {
+                    write!(f, "\nConfigured instances: {}", configured.join(", "))?;
+                }
+
+                Ok(())
+            }
+            CliError::OpenInstance { name, err } => write!(f, "Failed to open instance \"{}\": {}", name, err),
+            CliError::Config(err) => write!(f, "{}", err),
+            CliError::SaveConfig(err) => write!(f, "Failed to save the config: {}", err),
+            CliError::InstancesFile { path, err } => write!(f, "Failed to use the instances file {}: {}", path.to_string_lossy(), err),
+            CliError::ManifestFile { path, err } => write!(f, "Failed to use the manifest {}: {}", path.to_string_lossy(), err),
+            CliError::InvalidArguments(message) => f.write_str(message),
+            CliError::InvalidModId { id, reason } => write!(f, "Can't look up \"{}\": {}", id, reason),
+            CliError::ModNotFound { id, reason } => write!(f, "The mod \"{}\" was {}", id, reason),
+            CliError::NotInstalled(id) => write!(f, "The mod \"{}\" was not found.", id),
+            CliError::StillRequired(count) => write!(f, "{} installed mods require it, remove them too or use --force", count),
+            CliError::NoSuitableRelease(id) => write!(f, "No suitable releases of \"{}\" found", id),
+            CliError::Fetch(err) => write!(f, "{}", with_hint(format!("Failed to fetch mod: {}", err), err)),
+            CliError::Dependencies(err) => write!(f, "{}", with_hint(format!("Failed to process dependencies: {}", err), err)),
+            CliError::Download { id, err } => write!(f, "{}", with_hint(format!("Failed to download {}: {}", id, err), err.as_ref())),
+            CliError::Io { path, err } => write!(f, "Failed to access {}: {}", path.display(), err),
+            CliError::TooLarge { total, max } => write!(f, "The download of {} exceeds the limit of {}, raise it with --max-download",
+                                                        format_size(*total), format_size(*max)),
+            CliError::Locked(name) => write!(f, "Another fmods operation is in progress on the instance \"{}\", try again later", name),
+            CliError::Failed(count) => write!(f, "{} mods failed, see above", count),
+            CliError::UnknownFactorioVersion(path) =>
+                write!(f, "{}, check the path of the instance: {}", UNKNOWN_VERSION_MESSAGE, path.display())
+        }
+    }
+}
+
+impl std::error::Error for CliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CliError::OpenInstance { err, .. } => Some(err),
+            CliError::Config(err) => Some(err),
+            CliError::Fetch(err) => Some(err),
+            CliError::Dependencies(err) => Some(err),
+            CliError::Io { err, .. } => Some(err),
+            _ => None
+        }
+    }
+}
+
+const UNKNOWN_VERSION_MESSAGE: &str = "instance version couldn't be detected; mods can't be resolved";
+
+/// Appends what the user can do about `err` to `message` if it's a network problem.
+fn with_hint(message: String, err: &(dyn std::error::Error + 'static)) -> String {
+    match http::hint(err) {
+        Some(hint) => format!("{}; {}", message, hint),
+        None => message
+    }
+}
+
+/// Keeps other fmods processes from changing the instance until the lock is dropped.
+fn lock_instance(instance: &Instance, name: &str) -> Result<InstanceLock, CliError> {
+    match InstanceLock::try_acquire(instance) {
+        Ok(Some(lock)) => Ok(lock),
+        Ok(None) => Err(CliError::Locked(name.to_string())),
+        Err(err) => Err(CliError::Io { path: lock_path(instance), err })
+    }
+}
+
+/// `Failed` if some mods are in `failed`, which were already reported
+fn check_failures(failed: usize) -> Result<(), CliError> {
+    match failed {
+        0 => Ok(()),
+        count => Err(CliError::Failed(count))
+    }
+}
+
+const SPINNER_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Completes `--instance`, `remove` and `instance remove` with the values of `complete-values`,
+/// everything else is left to the script generated by clap.
+const BASH_VALUES_COMPLETION: &str = r#"
+_fmods_values() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}" kind= i instance=()
+    if [[ "$prev" == --instance ]]; then
+        kind=instances
+    elif [[ "$prev" == remove && "$cur" != -* ]]; then
+        if [[ "${COMP_WORDS[COMP_CWORD-2]}" == instance ]]; then kind=instances; else kind=mods; fi
+    fi
+
+    if [[ -z "$kind" ]]; then
+        _fmods "$@"
+        return
+    fi
+
+    for ((i = 1; i < COMP_CWORD - 1; i++)); do
+        [[ "${COMP_WORDS[i]}" == --instance ]] && instance=(--instance "${COMP_WORDS[i+1]}")
+    done
+    COMPREPLY=($(compgen -W "$(fmods "${instance[@]}" complete-values "$kind" 2>/dev/null)" -- "$cur"))
+}
+
+complete -F _fmods_values -o bashdefault -o default fmods
+"#;
+
+const FISH_VALUES_COMPLETION: &str = r#"
+complete -c fmods -l instance -x -a '(fmods complete-values instances 2>/dev/null)'
+complete -c fmods -n '__fish_seen_subcommand_from instance; and __fish_seen_subcommand_from remove' -x -a '(fmods complete-values instances 2>/dev/null)'
+complete -c fmods -n '__fish_seen_subcommand_from remove; and not __fish_seen_subcommand_from instance' -x -a '(fmods complete-values mods 2>/dev/null)'
+"#;
+
+/// Single status line redrawn in place on stderr while a long operation runs.
+/// Nothing is drawn unless stderr is a terminal, so redirected output stays clean.
+struct Spinner {
+    frame: usize,
+    width: usize,
+    enabled: bool,
+}
+
+impl Spinner {
+    fn new() -> Self {
+        Spinner { frame: 0, width: 0, enabled: stderr().is_terminal() }
+    }
+
+    fn update(&mut self, status: String) {
+        if !self.enabled {
+            return;
+        }
+
+        let line = format!("{} {}", SPINNER_FRAMES[self.frame % SPINNER_FRAMES.len()], status);
+        eprint!("\r{:width$}", line, width = self.width);
+        _ = stderr().flush();
+
+        self.frame += 1;
+        self.width = self.width.max(line.chars().count());
+    }
+
+    fn finish(&mut self) {
+        if self.width != 0 {
+            eprint!("\r{:width$}\r", "", width = self.width);
+            _ = stderr().flush();
+            self.width = 0;
+        }
+    }
+}
+
+/// Prints the phases and failures of the pipeline, and the mods being resolved on a spinner
+struct PrintObserver {
+    spinner: Mutex<Spinner>
+}
+
+impl PrintObserver {
+    fn new() -> Self {
+        PrintObserver { spinner: Mutex::new(Spinner::new()) }
+    }
+
+    fn status(&self, status: String) {
+        self.spinner.lock().unwrap().update(status);
+    }
+
+    fn finish(&self) {
+        self.spinner.lock().unwrap().finish();
+    }
 }
 
-fn save_config(config: &Config) {
-    config.save().unwrap();
+impl Observer for PrintObserver {
+    fn event(&self, event: Event) {
+        match event {
+            Event::Resolving(id) => self.status(format!("Resolving: {}", id)),
+            Event::PhaseStarted(Phase::Download) => println!("Downloading..."),
+            Event::PhaseStarted(Phase::Update) => println!("Updating..."),
+            Event::PhaseStarted(Phase::Prune) => println!("Pruning old versions..."),
+            Event::PhaseStarted(Phase::Remove) => println!("Removing conflicts..."),
+            Event::Failed { id, error } => println!("{}", with_hint(format!("Failed to download {}: {}", id, error), error)),
+            Event::Pruned(path) => println!("  {} {}", "Pruned".bright_yellow(), path.display()),
+            _ => {}
+        }
+    }
 }
 
+/// Asks until one of `variants` is entered. The last variant is the answer if the input ended,
+/// so a question read from a closed or already consumed stdin is declined instead of asked forever.
 fn choose(msg: String, variants: &[String]) -> String {
     loop {
         let mut input = String::new();
         println!("{}", msg);
-        stdin().read_line(&mut input).unwrap();
+        if stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            return variants.last().cloned().unwrap_or_default();
+        }
         input = input.to_ascii_lowercase().trim_end().to_string();
         for variant in variants {
             if &input == variant {
@@ -96,46 +749,137 @@ fn choose(msg: String, variants: &[String]) -> String {
     }
 }
 
-pub fn cli() {
-    let mut config = Config::load();
+/// Asks for one of the instance names, by name in any case or by its number in the list.
+/// None if an empty line is entered or the input ended.
+fn choose_instance(names: &[String]) -> Option<String> {
+    println!("Select instance (an empty line cancels)");
+    for (index, name) in names.iter().enumerate() {
+        println!("  {}. {}", index + 1, name.bright_yellow());
+    }
+
+    loop {
+        let mut input = String::new();
+        if stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            return None;
+        }
+
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+
+        // an instance may be named like a number, the name wins
+        let selected = names.iter().find(|x| x.eq_ignore_ascii_case(input))
+            .or_else(|| input.parse::<usize>().ok()?.checked_sub(1).and_then(|x| names.get(x)));
+
+        match selected {
+            Some(name) => return Some(name.clone()),
+            None => println!("There is no instance \"{}\", enter a name or a number from the list:", input)
+        }
+    }
+}
+
+fn confirmation_needed(mode: ConfirmMode, yes: bool, count: usize) -> bool {
+    let needed = match mode {
+        ConfirmMode::Always => true,
+        ConfirmMode::Never => false,
+        ConfirmMode::Threshold(threshold) => count > threshold,
+    };
+
+    needed && !yes
+}
+
+/// Asks to confirm an operation affecting `count` mods, unless `mode` or `--yes` allows to skip the question.
+fn confirm(mode: ConfirmMode, yes: bool, count: usize, msg: &str) -> bool {
+    if !confirmation_needed(mode, yes, count) {
+        return true;
+    }
+
+    choose(format!("{} ({}es/{}o)", msg, "y".bold(), "n".bold()), &["y".to_string(), "n".to_string()]) == "y"
+}
+
+/// Same as `confirm`, but restates the impact of `changes`. Plans removing mods need the full word `yes`.
+fn confirm_changes(mode: ConfirmMode, yes: bool, changes: &Changes) -> bool {
+    let count = changes.install.len() + changes.update.len() + changes.conflicts.len();
+    if !confirmation_needed(mode, yes, count) {
+        return true;
+    }
+
+    if changes.conflicts.is_empty() {
+        let msg = format!("This will install {} and update {} mods. Continue?", changes.install.len(), changes.update.len());
+        return choose(format!("{} ({}es/{}o)", msg, "y".bold(), "n".bold()), &["y".to_string(), "n".to_string()]) == "y";
+    }
+
+    let msg = format!("This will install {}, update {} and {} {} conflicting mods. Type {} to continue or {} to cancel:",
+                      changes.install.len(), changes.update.len(), "REMOVE".bright_red().bold(), changes.conflicts.len(),
+                      "yes".bold(), "no".bold());
+    choose(msg, &["yes".to_string(), "no".to_string()]) == "yes"
+}
 
-    let args = Args::parse();
+pub fn cli(args: Args) -> Result<(), CliError> {
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        // the report is still useful with a broken config, nothing is saved by it
+        Err(err) if args.command == Commands::Selfcheck => {
+            println!("{}", err);
+            Config::default()
+        }
+        Err(err) => return Err(CliError::Config(err))
+    };
+    if args.command != (Commands::Config { command: ConfigCommands::Validate }) {
+        warn_unknown_config_keys();
+    }
+    http::init(Duration::from_secs(args.timeout.unwrap_or(config.timeout)));
 
     let ask = (config.ask || args.ask) && !args.no_ask;
+    let jobs = args.jobs.unwrap_or(config.jobs).max(1);
+    let post_hook = args.post_hook.clone().or_else(|| config.post_install_command.clone());
 
     if let Commands::Instances {command} = &args.command {
         match command {
-            InstancesCommands::Add {name, path, replace, default } => {
+            InstancesCommands::Add {name, path, replace, default, factorio_version, no_validate } => {
                 if config.instances.contains_key(name) && !replace {
-                    println!("The instance \"{}\" already exists.", name);
                     if !ask {
-                        return;
+                        return Err(CliError::InstanceExists(name.clone()));
                     }
 
+                    println!("The instance \"{}\" already exists.", name);
+
                     let answer = choose(format!("Do you want replace instance? ({}es/{}o)", "y".bold(), "n".bold()),
                                         &["y".to_string(), "n".to_string()]);
                     match answer.as_str() {
-                        "n" => return,
+                        "n" => return Ok(()),
                         _ => {}
                     }
                 }
 
-                match Instance::new(path.clone()) {
-                    Ok(instance) => instance_info(&instance, name),
-                    Err(err) => return println!("Failed to open instance: {}", err)
+                // the instance is validated when a later command opens it
+                if *no_validate {
+                    println!("The instance \"{}\" is added without validation.", name);
+                } else {
+                    let instance = match factorio_version {
+                        Some(factorio_version) => Instance::new_mods_only(path.clone(), factorio_version.clone()),
+                        None => Instance::new(path.clone())
+                    };
+
+                    match instance {
+                        Ok(instance) => instance_info(&instance, name),
+                        Err(err) => return Err(CliError::OpenInstance { name: name.clone(), err })
+                    }
                 }
 
-                config.instances.insert(name.clone(), path.clone());
+                config.add_instance(name, path.clone(), factorio_version.clone());
                 if *default {
                     config.default_instance = Some(name.clone());
                 }
 
-                save_config(&config);
+                save_config(&config)?;
 
             }
             InstancesCommands::Remove {name} => {
                 if config.instances.contains_key(name) {
                     config.instances.remove(name);
+                    config.mods_only.remove(name);
 
                     if let Some(default_instance) = &config.default_instance {
                         if default_instance == name {
@@ -144,196 +888,2111 @@ pub fn cli() {
                     }
 
                     println!("The instance \"{}\" is removed", name);
-                    save_config(&config);
+                    save_config(&config)?;
                 } else {
-                    println!("A instance with the name \"{}\" was not found.", name);
+                    return Err(instance_not_found(&config, name));
                 }
             }
-            InstancesCommands::List => {
+            InstancesCommands::List { verbose } => {
                 println!("Default instance: {}", match &config.default_instance {
                     None => "not specified".bright_black(),
                     Some(str) => str.bright_yellow()
                 });
                 println!("Saved {} instances:", config.instances.len().to_string().bright_blue());
-                for instance in config.instances {
+                for instance in &config.instances {
                     println!("  {} -> {}", instance.0.bright_yellow(), instance.1.to_string_lossy().bright_yellow());
+
+                    if *verbose {
+                        match config.open_instance(instance.0).unwrap() {
+                            Ok(instance) => println!("    Factorio {}, {} mods", instance.version.to_string().bright_yellow(),
+                                                     instance.mods().len().to_string().bright_blue()),
+                            Err(err) => println!("    {}", format!("broken: {}", err).bright_red())
+                        }
+                    }
                 }
             }
             InstancesCommands::Default {name} => {
                 if config.instances.contains_key(name) {
                     config.default_instance = Some(name.clone());
                     println!("The instance \"{}\" is default now.", name);
-                    save_config(&config);
-                    return;
+                    save_config(&config)?;
+                    return Ok(());
                 }
 
-                println!("A instance with the name \"{}\" was not found.", name);
+                return Err(instance_not_found(&config, name));
             }
             InstancesCommands::UnsetDefault => {
                 config.default_instance = None;
                 println!("The default instance no specified now.");
-                save_config(&config);
+                save_config(&config)?;
             }
-        }
+            InstancesCommands::Export { file } => {
+                let registry = config.export_instances(dirs::home_dir().as_deref());
+                registry.save(file).map_err(|err| CliError::InstancesFile { path: file.clone(), err })?;
 
-        return;
-    }
+                println!("Exported {} instances to {}", registry.instances.len().to_string().bright_blue(),
+                         file.to_string_lossy().bright_yellow());
+            }
+            InstancesCommands::Import { file, prefix, replace } => {
+                let registry = InstanceRegistry::load(file).map_err(|err| CliError::InstancesFile { path: file.clone(), err })?;
+                let base = prefix.clone().or_else(dirs::home_dir).unwrap_or_default();
+                let mut imported = 0;
 
-    let instance_name = {
-        let mut option = args.instance.clone();
+                for (name, entry) in registry.instances {
+                    if config.instances.contains_key(&name) && !replace {
+                        println!("  {} already exists, skipped", name.bright_yellow());
+                        continue;
+                    }
 
-        if option == None {
-            option = config.default_instance.clone();
-        }
+                    let path = expand_home(&entry.path, &base);
+                    if path.exists() {
+                        println!("  {} -> {}", name.bright_yellow(), path.to_string_lossy().bright_yellow());
+                    } else {
+                        println!("  {} -> {} {}", name.bright_yellow(), path.to_string_lossy().bright_yellow(),
+                                 "(does not exist)".bright_red());
+                    }
 
-        if option == None && ask {
-            println!("Select instance");
-            for instance in &config.instances {
-                println!("  {}", instance.0.bright_yellow());
-            }
+                    config.add_instance(&name, path, entry.factorio_version);
+                    imported += 1;
+                }
 
-            let mut name = String::new();
-            stdin().read_line(&mut name).unwrap();
-            option = Some(name.trim_end().to_string());
+                save_config(&config)?;
+                println!("Imported {} instances", imported.to_string().bright_blue());
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Commands::Config { command } = &args.command {
+        match command {
+            ConfigCommands::Get { key } => println!("{}", config.get(key).map_err(CliError::Config)?),
+            ConfigCommands::Set { key, value } => {
+                config.set(key, value).map_err(CliError::Config)?;
+                save_config(&config)?;
+            }
+            ConfigCommands::Validate => {
+                let path = config_path();
+                let text = match std::fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        println!("There is no config file, the defaults are used");
+                        return Ok(());
+                    }
+                    Err(err) => return Err(CliError::Io { path, err })
+                };
+
+                let keys = config::unknown_keys(&text).map_err(CliError::Config)?;
+                for key in &keys {
+                    println!("{}", unknown_key_message(key));
+                }
+
+                if keys.is_empty() {
+                    println!("{}", "The config is valid".bright_green());
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Commands::Completions { shell } = &args.command {
+        generate(*shell, &mut Args::command(), "fmods", &mut stdout());
+        match shell {
+            Shell::Bash => print!("{}", BASH_VALUES_COMPLETION),
+            Shell::Fish => print!("{}", FISH_VALUES_COMPLETION),
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    if let Commands::Pin { name, range } = &args.command {
+        match (name, range) {
+            (Some(name), Some(range)) => {
+                config.pins.insert(name.clone(), range.to_string());
+                save_config(&config)?;
+                println!("The mod \"{}\" is pinned to {}", name, range.to_string().bright_yellow());
+            }
+            (Some(name), None) => match config.pins.get(name) {
+                Some(range) => println!("{} {}", name.bright_yellow(), range),
+                None => println!("The mod \"{}\" is not pinned", name)
+            },
+            (None, _) => {
+                let mut pins: Vec<(&String, &String)> = config.pins.iter().collect();
+                pins.sort();
+
+                println!("Pinned {} mods:", pins.len().to_string().bright_blue());
+                for (name, range) in pins {
+                    println!("  {} {}", name.bright_yellow(), range);
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Commands::Unpin { name } = &args.command {
+        match config.pins.remove(name) {
+            Some(_) => {
+                save_config(&config)?;
+                println!("The mod \"{}\" is not pinned anymore", name);
+            }
+            None => println!("The mod \"{}\" is not pinned", name)
+        }
+
+        return Ok(());
+    }
+
+    // the config may be broken, that's what the report is for
+    if let Commands::Selfcheck = &args.command {
+        selfcheck(&config, args.timeout.unwrap_or(config.timeout), jobs, !args.no_cache, args.official_portal || config.official_portal);
+        return Ok(());
+    }
+
+    let pins = config.pins().map_err(CliError::Config)?;
+
+    if let Commands::Plan { manifest, mods } = &args.command {
+        let instance = Manifest::load(manifest).map(|x| Instance::from_manifest(manifest.clone(), x))
+            .map_err(|err| CliError::ManifestFile { path: manifest.clone(), err })?;
+
+        let cache = (!args.no_cache).then(|| Cache::new(Duration::from_secs(config.cache_ttl)))
+            .map(|cache| if args.refresh { cache.refreshed() } else { cache });
+        let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+
+        let targets = parse_download_targets(mods.clone())
+            .map_err(|err| CliError::InvalidArguments(format!("Invalid version: {}", err)))?;
+
+        let mut roots = Vec::new();
+        for (name, version) in targets {
+            let mod_info = get_root_mod(&factorio_api, name)?;
+
+            let version = match version.unwrap_or(VersionRequest::Latest) {
+                VersionRequest::Latest => mod_info.releases.last().unwrap().version.clone(),
+                VersionRequest::Exact(version) => version,
+            };
+
+            roots.push((mod_info.name, version));
+        }
+
+        let observer = PrintObserver::new();
+        let plan = pipeline::plan(&factorio_api, &instance, roots, &observer);
+        observer.finish();
+
+        let failed: Vec<(String, String)> = plan.failed.into_iter()
+            .map(|(name, err)| (name, with_hint(format!("failed to process dependencies: {}", err), &err)))
+            .collect();
+
+        print_changes(&plan.changes);
+        print_failures(&failed);
+
+        return check_failures(failed.len());
+    }
+
+    if let Commands::History { since } = &args.command {
+        let cutoff = since.map(|x| x.cutoff());
+
+        for entry in history::load() {
+            if args.instance.as_ref().is_some_and(|x| x != &entry.instance) {
+                continue
+            }
+
+            if cutoff.is_some_and(|x| entry.timestamp < x) {
+                continue
+            }
+
+            let versions = match (&entry.old_version, &entry.new_version) {
+                (Some(old), Some(new)) => format!("{} -> {}", old, new),
+                (Some(version), None) | (None, Some(version)) => version.to_string(),
+                (None, None) => String::new()
+            };
+
+            println!("{} [{}] {} {} {}", history::format_timestamp(entry.timestamp), entry.instance.bright_blue(),
+                     entry.action, entry.mod_id.bright_yellow(), versions.bright_yellow());
+        }
+
+        return Ok(());
+    }
+
+    if let Commands::CompleteValues { kind } = &args.command {
+        match kind {
+            CompletionKind::Instances => {
+                for name in config.instances.keys() {
+                    println!("{}", name);
+                }
+            }
+            CompletionKind::Mods => {
+                let instance = args.instance.clone()
+                    .or_else(instance_from_env)
+                    .or(config.default_instance.clone())
+                    .and_then(|name| config.open_instance(&name));
+
+                if let Some(Ok(instance)) = instance {
+                    for installed_mod in instance.mods() {
+                        println!("{}", installed_mod.name);
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Commands::Diff { first, second, format } = &args.command {
+        let diff = ModsDiff::compute(open_instance(&config, first)?.mods(), open_instance(&config, second)?.mods());
+
+        match format {
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+                return Ok(());
+            }
+            OutputFormat::Csv => {
+                println!("{}", csv_row(&["name", first.as_str(), second.as_str()]));
+                for (name, version) in &diff.only_first {
+                    println!("{}", csv_row(&[name.clone(), version.to_string(), String::new()]));
+                }
+                for (name, version) in &diff.only_second {
+                    println!("{}", csv_row(&[name.clone(), String::new(), version.to_string()]));
+                }
+                for (name, first_version, second_version) in &diff.different {
+                    println!("{}", csv_row(&[name.clone(), first_version.to_string(), second_version.to_string()]));
+                }
+                return Ok(());
+            }
+            OutputFormat::Text => {}
+        }
+
+        if diff.is_empty() {
+            println!("{}", "The instances have the same mods".bright_green());
+            return Ok(());
+        }
+
+        println!("Only in {} ({}):", first.bright_blue(), diff.only_first.len().to_string().bright_blue());
+        for (name, version) in &diff.only_first {
+            println!("  {} {}", name.bright_yellow(), version.to_string().bright_yellow());
+        }
+
+        println!("Only in {} ({}):", second.bright_blue(), diff.only_second.len().to_string().bright_blue());
+        for (name, version) in &diff.only_second {
+            println!("  {} {}", name.bright_yellow(), version.to_string().bright_yellow());
+        }
+
+        println!("Different versions ({}):", diff.different.len().to_string().bright_blue());
+        for (name, first_version, second_version) in &diff.different {
+            println!("  {} {} / {}", name.bright_yellow(), first_version.to_string().bright_yellow(),
+                     second_version.to_string().bright_yellow());
+        }
+
+        return Ok(());
+    }
+
+    if let Commands::Sync { source, target, remove_extra } = &args.command {
+        let source_instance = open_instance(&config, source)?;
+        let target_instance = open_instance(&config, target)?;
+        let _lock = lock_instance(&target_instance, target)?;
+
+        if PartialVersion::major_minor(&source_instance.version) != PartialVersion::major_minor(&target_instance.version) {
+            println!("{}", format!("The instances have different Factorio versions ({} and {}), some mods may not work",
+                                   source_instance.version, target_instance.version).bright_red());
+        }
+
+        // the source's mod set is already complete, its versions are installed as they are
+        let diff = ModsDiff::compute(source_instance.mods(), target_instance.mods());
+        let changes = Changes {
+            install: diff.only_first.into_iter().map(|(id, version)| InstallChange { id, version }).collect(),
+            update: diff.different.into_iter()
+                .map(|(id, new_version, old_version)| UpdateChange { id, old_version, new_version })
+                .collect(),
+            conflicts: if *remove_extra { diff.only_second.into_iter().map(|x| x.0).collect() } else { vec![] },
+            optional: vec![],
+            held: vec![],
+        };
+
+        print_changes(&changes);
+
+        if changes.install.is_empty() && changes.update.is_empty() && changes.conflicts.is_empty() {
+            println!("{}", "The target already has the mods of the source".bright_green());
+            return Ok(());
+        }
+
+        if !confirm_changes(config.confirm.download, args.yes, &changes) {
+            return Ok(());
+        }
+
+        let downloader = Downloader::new(&target_instance)
+            .with_source(download_source(&config, args.official_portal, &target_instance));
+        let changed = apply_changes(&target_instance, target, &downloader, changes, jobs, false);
+        verify_installation(&target_instance);
+
+        run_post_install_hook(post_hook.as_deref(), target, &changed?);
+        return Ok(());
+    }
+
+    if let Info { all_instances: true, .. } | Commands::List { all_instances: true, .. } = &args.command {
+        let mut names: Vec<&String> = config.instances.keys().collect();
+        names.sort();
+
+        for name in names {
+            println!("{}", format!("== {} ==", name).bold());
+
+            match config.open_instance(name).unwrap() {
+                Ok(instance) => match &args.command {
+                    Commands::List { pattern, .. } => list_mods(&instance, pattern.as_deref(), OutputFormat::Text),
+                    _ => instance_info(&instance, name)
+                },
+                Err(err) => println!("{}", format!("Skipped, failed to open the instance: {}", err).bright_red())
+            }
+
+            println!();
+        }
+
+        return Ok(());
+    }
+
+    if config.instances.is_empty() {
+        return Err(CliError::NoInstances);
+    }
+
+    let instance_name = {
+        let mut option = args.instance.clone();
+
+        if option == None {
+            option = instance_from_env();
+        }
+
+        if option == None {
+            option = config.default_instance.clone();
+        }
+
+        if option == None && ask {
+            let mut names: Vec<String> = config.instances.keys().cloned().collect();
+            names.sort();
+            option = choose_instance(&names);
         }
 
         if let Some(name) = option {
             name
         } else {
-            println!("No instance selected.");
-            return;
+            return Err(CliError::NoInstanceSelected);
         }
     };
 
-    let instance = match config.instances.get(&instance_name) {
-        None => return println!("A instance with the name \"{}\" was not found.", instance_name),
-        Some(value) => match Instance::new(value.clone()) {
-            Ok(instance) => instance,
-            Err(err) => return println!("Failed to open instance \"{}\": {}", instance_name, err)
-        },
-    };
+    let instance = open_instance(&config, &instance_name)?;
+
+    let mutating = matches!(args.command, Commands::Download { .. } | Commands::InstallBundle { .. } | Commands::Remove { .. }
+        | Commands::Repair { .. } | Commands::Update { format: OutputFormat::Text, .. } | Commands::Migrate { apply: true, .. } | Commands::Check | Commands::Undo
+        | Commands::Enable { dry_run: false, .. } | Commands::Disable { dry_run: false, .. });
+    let _lock = if mutating { Some(lock_instance(&instance, &instance_name)?) } else { None };
+
+    let cache = (!args.no_cache).then(|| Cache::new(Duration::from_secs(config.cache_ttl)))
+        .map(|cache| if args.refresh { cache.refreshed() } else { cache });
+    let source = download_source(&config, args.official_portal, &instance);
 
     match args.command {
-        Info => instance_info(&instance, &instance_name),
-        Commands::List => {
-            println!("Installed {} mods:", instance.mods.len().to_string().bright_blue());
-            for mod_info in &instance.mods {
-                println!("  {} {}", mod_info.name.bright_yellow(), mod_info.version.to_string().bright_yellow());
+        Info { mod_name: Some(name), .. } => match instance.mods().iter().find(|x| x.name == name) {
+            Some(installed_mod) => installed_mod_info(&instance, installed_mod),
+            None => return Err(CliError::NotInstalled(name))
+        },
+        Info { .. } => instance_info(&instance, &instance_name),
+        Commands::List { pattern, format, .. } => list_mods(&instance, pattern.as_deref(), format),
+        Commands::Download { mods, from_file, only_missing, limit, sha1, file_name, prune, max_download, major_compat, deps_only, no_deps,
+                instance_version_check, reconcile } => {
+            if !instance.has_factorio_version() {
+                if instance_version_check {
+                    return Err(CliError::UnknownFactorioVersion(instance.path.clone()));
+                }
+
+                println!("{} {}, check the path of the instance: {}", "Warning:".bright_red().bold(),
+                         UNKNOWN_VERSION_MESSAGE.bright_red().bold(), instance.path.display());
             }
-        }
-        Commands::Download { name, mod_version: version } => {
-            let factorio_api = FactorioApi::new(&instance);
 
-            let mod_info = match factorio_api.get_mod(&name) {
-                Ok(val) => val,
-                Err(err) => return println!("Failed to fetch mod: {}", err)
+            let mut targets = match parse_download_targets(mods) {
+                Ok(targets) => targets,
+                Err(err) => return Err(CliError::InvalidArguments(format!("Invalid version: {}", err)))
             };
 
-            if mod_info.releases.len() == 0 {
-                println!("No suitable releases found");
-                return;
+            if let Some(path) = &from_file {
+                for line in read_mods_file(path)? {
+                    match parse_download_target(line) {
+                        Ok(target) => targets.push(target),
+                        Err(err) => return Err(CliError::InvalidArguments(format!("Invalid version: {}", err)))
+                    }
+                }
             }
 
-            let version = match version {
-                Some(val) => val,
-                None => {
-                    println!("Select version:");
-                    for release in mod_info.releases {
-                        println!("  {}", release.version.to_string().bright_yellow());
+            let selector = match (sha1, file_name) {
+                (Some(sha1), _) => Some(ReleaseSelector::Sha1(sha1)),
+                (None, Some(file_name)) => Some(ReleaseSelector::FileName(file_name)),
+                (None, None) => None
+            };
+
+            if selector.is_some() && (targets.len() != 1 || targets[0].1.is_some()) {
+                return Err(CliError::InvalidArguments("--sha1 and --file-name can only be used with a single mod without a version".to_string()));
+            }
+
+            let mut checksums = HashMap::new();
+
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins)
+                .with_major_compat(major_compat);
+            // a listed mod is never asked for, stdin may be the list itself
+            let single = targets.len() == 1 && from_file.is_none();
+
+            let mut roots = Vec::new();
+            let mut failed = Vec::new();
+            // dependencies of the selected releases, only checked with --no-deps
+            let mut declared = Vec::new();
+
+            let targets: Vec<(String, Option<VersionRequest>)> = targets.into_iter()
+                .filter(|(name, _)| match invalid_id_message(&factorio_api, name) {
+                    Some(reason) => {
+                        failed.push((name.clone(), reason));
+                        false
+                    }
+                    None => true
+                })
+                .collect();
+
+            let names: Vec<String> = targets.iter().map(|x| x.0.clone()).collect();
+            let mod_infos = factorio_api.get_mods(&names, jobs);
+
+            for ((name, version), mod_info) in targets.into_iter().zip(mod_infos) {
+                let mod_info = match mod_info {
+                    Ok(val) => val,
+                    Err(ureq::Error::StatusCode(404)) => {
+                        let reason = not_found_message(&factorio_api, &name);
+                        failed.push((name, reason));
+                        continue
+                    }
+                    Err(err) => {
+                        failed.push((name, with_hint(format!("failed to fetch mod: {}", err), &err)));
+                        continue
+                    }
+                };
+
+                let name = if mod_info.name != name {
+                    println!("The mod \"{}\" is now \"{}\"", name, mod_info.name.bright_yellow());
+                    mod_info.name.clone()
+                } else {
+                    name
+                };
+
+                if mod_info.releases.is_empty() {
+                    failed.push((name, "no suitable releases found".to_string()));
+                    continue
+                }
+
+                if let Some(selector) = &selector {
+                    match mod_info.find_release(selector) {
+                        Some(release) => {
+                            if let Some(sha1) = &release.sha1 {
+                                checksums.insert(name.clone(), sha1.clone());
+                            }
+                            declared.push((name.clone(), release.info_json.dependencies.clone()));
+                            roots.push((name, release.version.clone()));
+                        }
+                        None => failed.push((name, "no compatible release matches the archive".to_string()))
                     }
 
-                    let mut name = String::new();
-                    stdin().read_line(&mut name).unwrap();
+                    continue
+                }
+
+                // the version is only asked for when downloading a single mod
+                let version = match version {
+                    Some(val) => val,
+                    None if single => pick_version(&mod_info.releases, limit),
+                    None => VersionRequest::Latest
+                };
 
-                    match Version::from_str(name.trim_end()) {
-                        Ok(ver) => ver,
-                        Err(err) => return println!("Failed to parse version: {}", err)
+                let version = match version {
+                    VersionRequest::Latest => mod_info.releases.last().unwrap().version.clone(),
+                    VersionRequest::Exact(version) => version,
+                };
+
+                // without resolving, nothing else would notice a missing release
+                if no_deps {
+                    match mod_info.releases.iter().find(|x| x.version == version) {
+                        Some(release) => declared.push((name.clone(), release.info_json.dependencies.clone())),
+                        None => {
+                            failed.push((name, format!("no compatible release {}", version)));
+                            continue
+                        }
                     }
                 }
+
+                roots.push((name, version));
+            }
+
+            if roots.is_empty() {
+                print_failures(&failed);
+                return check_failures(failed.len());
+            }
+
+            let root_names: Vec<String> = roots.iter().map(|x| x.0.clone()).collect();
+
+            let plan = if no_deps {
+                warn_missing_dependencies(&instance, &roots, &declared);
+                pipeline::plan_without_dependencies(&instance, roots)
+            } else {
+                println!("Processing dependencies...");
+
+                let observer = PrintObserver::new();
+                let plan = pipeline::plan(&factorio_api, &instance, roots, &observer);
+                observer.finish();
+                warn_early_upgrade(&factorio_api, &instance);
+
+                plan
+            };
+
+            failed.extend(plan.failed.into_iter()
+                .map(|(name, err)| (name, with_hint(format!("failed to process dependencies: {}", err), &err))));
+
+            let mut changes = plan.changes;
+            if only_missing {
+                changes.hold_updates();
+            }
+            // the roots still select the versions of their dependencies
+            if deps_only {
+                changes.skip(&root_names);
+            }
+
+            print_changes(&changes);
+            print_failures(&failed);
+
+            let count = changes.install.len() + changes.update.len() + changes.conflicts.len();
+            if count == 0 && !failed.is_empty() {
+                return check_failures(failed.len());
+            }
+
+            let max_download = match (max_download, &config.max_download) {
+                (Some(max), _) => Some(max),
+                (None, Some(max)) => Some(parse_size(max).map_err(|_| CliError::Config(config::Error::InvalidValue {
+                    key: "max_download".to_string(), expected: "a size like 500MiB or 2GB"
+                }))?),
+                (None, None) => None
+            };
+
+            let downloader = Downloader::new(&instance).with_source(source.clone()).with_checksums(checksums);
+            if let Some(max) = max_download {
+                check_download_size(&downloader, &changes, max, jobs)?;
+            }
+
+            if !confirm_changes(config.confirm.download, args.yes, &changes) {
+                return Ok(());
+            }
+
+            let installed: Vec<(String, Version)> = changes.install.iter().map(|x| (x.id.clone(), x.version.clone()))
+                .chain(changes.update.iter().map(|x| (x.id.clone(), x.new_version.clone())))
+                .collect();
+
+            let changed = apply_changes(&instance, &instance_name, &downloader, changes, jobs, prune);
+            verify_installation(&instance);
+
+            if reconcile {
+                print_mismatches(&pipeline::reconcile(&factorio_api, &instance, &installed));
+            }
+
+            let changed = changed?;
+            check_failures(failed.len())?;
+            run_post_install_hook(post_hook.as_deref(), &instance_name, &changed);
+        }
+        Commands::Bundle { name, mod_version, out } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+            let mod_info = get_root_mod(&factorio_api, name)?;
+
+            let version = match mod_version.unwrap_or_else(|| pick_version(&mod_info.releases, 10)) {
+                VersionRequest::Latest => mod_info.releases.last().unwrap().version.clone(),
+                VersionRequest::Exact(version) => version,
             };
 
             println!("Processing dependencies...");
 
-            let dependencies = match process_dependencies(&factorio_api, &instance, name, version) {
-                Ok(vec) => vec,
-                Err(err) => return println!("Failed to process dependencies: {}", err),
+            // the bundle has to be complete, no matter what is installed into the instance
+            let bare = instance.without_mods();
+            let mut spinner = Spinner::new();
+            let mut resolution = process_dependencies_of(&factorio_api, &bare, vec![(mod_info.name, version)],
+                                                         &mut |id| spinner.update(format!("Resolving: {}", id)));
+            spinner.finish();
+
+            if let Some((_, err)) = resolution.failed.pop() {
+                return Err(CliError::Dependencies(err));
+            }
+
+            let mods: Vec<(String, Version)> = resolution.dependencies.into_iter()
+                .filter(|x| x.dependency_type == DependencyType::Require && !is_mod_game_content(&x.mod_id))
+                .filter_map(|x| Some((x.mod_id, x.version?)))
+                .collect();
+
+            if let Err(err) = create_dir_all(&out) {
+                return Err(CliError::Io { path: out, err });
+            }
+
+            println!("Downloading {} mods...", mods.len().to_string().bright_blue());
+
+            let downloader = Downloader::new(&instance).with_source(source.clone());
+            let results = parallel_map(&mods, jobs, |(id, version)| downloader.save_archive(id, version, &out));
+            let mut failed = 0;
+
+            for ((id, version), result) in mods.iter().zip(results) {
+                match result {
+                    Ok(path) => println!("  {} {} -> {}", id.bright_yellow(), version.to_string().bright_yellow(), path.display()),
+                    Err(err) => {
+                        println!("  {} {} {}", id.bright_yellow(), version.to_string().bright_yellow(),
+                                 format!("failed: {}", err).bright_red());
+                        failed += 1;
+                    }
+                }
+            }
+
+            check_failures(failed)?;
+        }
+        Commands::Deps { name, mod_version, dot, depth } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+            let mod_info = get_root_mod(&factorio_api, name)?;
+
+            let version = match mod_version.unwrap_or(VersionRequest::Latest) {
+                VersionRequest::Latest => mod_info.releases.last().unwrap().version.clone(),
+                VersionRequest::Exact(version) => version,
             };
 
-            let mut changes = Changes::compute(&instance, &dependencies);
+            // the tree of the mod itself, installed mods would cut it short
+            let bare = instance.without_mods();
+            let mut spinner = Spinner::new();
+            // the graph is meant to be piped into other tools, nothing else goes with it
+            let mut resolution = process_dependencies_of(&factorio_api, &bare, vec![(mod_info.name.clone(), version)],
+                                                         &mut |id| if !dot { spinner.update(format!("Resolving: {}", id)) });
+            spinner.finish();
 
-            println!("Install ({}):", changes.install.len().to_string().bright_green());
-            for install in &changes.install {
-                println!("  {} {}", install.id.to_string().bright_yellow(), install.version.to_string().bright_yellow());
+            if let Some((_, err)) = resolution.failed.pop() {
+                return Err(CliError::Dependencies(err));
             }
 
-            println!("Update ({}):", changes.update.len().to_string().bright_yellow());
-            for update in &changes.update {
-                println!("  {} {} -> {}", update.id.to_string().bright_yellow(), update.old_version.to_string().bright_yellow(),
-                    update.new_version.to_string().bright_yellow()
-                );
+            if dot {
+                print!("{}", dependencies_dot(&resolution));
+                return Ok(());
+            }
+
+            for line in dependency_tree(&resolution, &mod_info.name, depth) {
+                println!("{}", line);
+            }
+        }
+        Commands::Changelog { name, from_version } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+            let mod_info = match factorio_api.get_mod_unfiltered(&name) {
+                Ok(mod_info) => mod_info,
+                Err(ureq::Error::StatusCode(404)) => return Err(CliError::InvalidArguments(not_found_message(&factorio_api, &name))),
+                Err(err) => return Err(CliError::Fetch(err))
+            };
+
+            let from = from_version.or_else(|| instance.mods().iter().find(|x| x.name == mod_info.name).map(|x| x.version.clone()));
+
+            let mut entries: Vec<ChangelogEntry> = parse_changelog(mod_info.changelog.as_deref().unwrap_or_default()).into_iter()
+                .filter(|x| from.as_ref().is_none_or(|from| &x.version > from))
+                .collect();
+            entries.sort_by(|a, b| b.version.cmp(&a.version));
+
+            if entries.is_empty() {
+                match &from {
+                    Some(from) => println!("No changes after {}", from.to_string().bright_yellow()),
+                    None => println!("The mod has no changelog")
+                }
             }
 
-            println!("Conflicts ({}):", changes.conflicts.len().to_string().bright_red());
-            for conflict in &changes.conflicts {
-                println!("  {}", conflict.bright_yellow());
+            for entry in entries {
+                match &entry.date {
+                    Some(date) => println!("Version {} ({})", entry.version.to_string().bright_yellow(), date),
+                    None => println!("Version {}", entry.version.to_string().bright_yellow())
+                }
+
+                for (category, items) in entry.sections {
+                    if !category.is_empty() {
+                        println!("  {}:", category.bright_blue());
+                    }
+                    for item in items {
+                        println!("    - {}", item);
+                    }
+                }
             }
+        }
+        Commands::Manifest { out } => {
+            let manifest = instance.manifest();
+            manifest.save(&out).map_err(|err| CliError::ManifestFile { path: out.clone(), err })?;
+
+            println!("Saved Factorio {} and {} mods to {}", manifest.factorio_version.to_string().bright_yellow(),
+                     manifest.mods.len().to_string().bright_blue(), out.to_string_lossy().bright_yellow());
+        }
+        Commands::InstallBundle { dir } => {
+            let entries = match read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) => return Err(CliError::Io { path: dir, err })
+            };
+
+            let mut archives: Vec<PathBuf> = entries.flatten()
+                .map(|x| x.path())
+                .filter(|x| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("zip")))
+                .collect();
+            archives.sort();
+
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+            let mut accepted = Vec::new();
+            let mut invalid = Vec::new();
+
+            for path in archives {
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+                let info = match read_archive_info(&path) {
+                    Ok(info) => info,
+                    Err(err) => {
+                        invalid.push((file_name, err.to_string()));
+                        continue
+                    }
+                };
+
+                let release = ModRelease {
+                    version: info.version.clone(),
+                    info_json: ModReleaseInfoJson {
+                        dependencies: info.dependencies.clone(),
+                        factorio_version: info.factorio_version.clone(),
+                    },
+                    ..Default::default()
+                };
 
-            let downloader = Downloader::new(&instance);
+                if !factorio_api.is_release_compatible(&release) {
+                    invalid.push((file_name, format!("incompatible, made for Factorio {}", PartialVersion::major_minor(&info.factorio_version))));
+                    continue
+                }
 
-            let answer = choose(format!("Proceed? ({}es/{}o)", "y".bold(), "n".bold()),
-                                &["y".to_string(), "n".to_string()]);
-            match answer.as_str() {
-                "n" => return,
-                _ => {}
+                accepted.push((path, info));
             }
 
-            println!("Downloading...");
-            for install in take(&mut changes.install) {
-                if let Err(err) = downloader.download(install.id, install.version) {
-                    println!("Failed to download: {}", err);
-                    return;
+            // dependencies are expected in the bundle itself or among the installed mods
+            let mut missing = Vec::new();
+            for (_, info) in &accepted {
+                for dependency in &info.dependencies {
+                    if dependency.dependency_type != DependencyType::Require || is_mod_game_content(&dependency.mod_id) {
+                        continue
+                    }
+
+                    let satisfies = |version: &Version| dependency.accepts(version);
+                    let present = accepted.iter().any(|(_, x)| x.name == dependency.mod_id && satisfies(&x.version)) ||
+                        instance.mods().iter().any(|x| x.name == dependency.mod_id && satisfies(&x.version));
+
+                    if !present {
+                        missing.push(format!("{} (required by {})", dependency, info.name));
+                    }
                 }
             }
 
-            println!("Updating...");
-            for update in take(&mut changes.update) {
-                instance.remove_mod(update.id.as_str());
+            println!("Install ({}):", accepted.len().to_string().bright_green());
+            for (_, info) in &accepted {
+                println!("  {} {}", info.name.bright_yellow(), info.version.to_string().bright_yellow());
+            }
+
+            print_failures(&invalid);
 
-                if let Err(err) = downloader.download(update.id, update.new_version) {
-                    println!("Failed to download: {}", err);
-                    return;
+            if !missing.is_empty() {
+                println!("Missing dependencies ({}):", missing.len().to_string().bright_red());
+                for dependency in &missing {
+                    println!("  {}", dependency.bright_yellow());
                 }
             }
 
-            println!("Removing conflicts...");
-            for conflict in take(&mut changes.conflicts) {
-                instance.remove_mod(conflict.as_str());
+            if accepted.is_empty() || !confirm(config.confirm.download, args.yes, accepted.len(), "Proceed?") {
+                return Ok(());
+            }
+
+            let downloader = Downloader::new(&instance).with_source(source.clone());
+            let mut changed = Vec::new();
+            let mut failed = 0;
+
+            for (path, info) in accepted {
+                let old_version = instance.mods().iter().find(|x| x.name == info.name).map(|x| x.version.clone());
+
+                if let Err(err) = downloader.install_archive(&path) {
+                    println!("Failed to install {}: {}", info.name, err);
+                    failed += 1;
+                    continue
+                }
+
+                // the installed version is only removed once the new one is in place
+                instance.prune_mod(&info.name, &info.version);
+
+                let action = if old_version.is_some() { Action::Update } else { Action::Install };
+                _ = history::record(&HistoryEntry::new(&instance_name, action, &info.name, old_version, Some(info.version)));
+                changed.push(info.name);
             }
 
             println!("{}", "\nDone!".bright_green().bold());
+            verify_installation(&instance);
+
+            check_failures(failed)?;
+            run_post_install_hook(post_hook.as_deref(), &instance_name, &changed);
         }
-        Commands::Remove { name } => {
-            if let Some(_) = instance.mods.iter().find(|x| x.name == name) {
-                instance.remove_mod(name.as_str());
-                println!("The mod \"{}\" was removed", name);
-            } else {
-                println!("The mod \"{}\" was not found.", name);
+        Commands::Remove { name, force } => {
+            let glob = is_glob(&name);
+            let mut names: Vec<String> = instance.mods().iter()
+                .filter(|x| if glob { glob_match(&name, &x.name) } else { x.name == name })
+                .map(|x| x.name.clone())
+                .collect();
+            names.sort();
+            names.dedup();
+
+            if names.is_empty() {
+                return Err(CliError::NotInstalled(name));
+            }
+
+            // mods removed together don't need each other anymore
+            let dependents: Vec<(&InstalledMod, &Dependency)> = names.iter()
+                .flat_map(|x| required_by(instance.mods(), x))
+                .filter(|(x, _)| !names.contains(&x.name))
+                .collect();
+
+            if !dependents.is_empty() {
+                println!("Required by ({}):", dependents.len().to_string().bright_red());
+                for (installed, dependency) in &dependents {
+                    println!("  {} {} ({})", installed.name.bright_yellow(), installed.version.to_string().bright_yellow(), dependency);
+                }
+
+                if !force {
+                    return Err(CliError::StillRequired(dependents.len()));
+                }
             }
-        }
-        _ => {}
-    }
 
+            // a pattern may match more than expected, its matches are always confirmed
+            let confirmed = if glob {
+                println!("Matched ({}):", names.len().to_string().bright_blue());
+                for name in &names {
+                    println!("  {}", name.bright_yellow());
+                }
+
+                confirm(ConfirmMode::Always, args.yes, names.len(), &format!("Remove {} mods?", names.len()))
+            } else {
+                confirm(config.confirm.remove, args.yes, 1, &format!("Remove \"{}\"?", name))
+            };
+
+            if !confirmed {
+                return Ok(());
+            }
+
+            for name in names {
+                let version = instance.mods().iter().find(|x| x.name == name).map(|x| x.version.clone());
+                instance.remove_mod(name.as_str());
+                _ = history::record(&HistoryEntry::new(&instance_name, Action::Remove, &name, version, None));
+
+                println!("The mod \"{}\" was removed", name);
+            }
+        }
+        Commands::Dependents { name, optional } => {
+            let installed = instance.mods().iter().find(|x| x.name == name).map(|x| x.version.clone())
+                .or_else(|| instance.game_content_versions.get(&name).cloned());
+            let installed = match installed {
+                Some(version) => version,
+                None => return Err(CliError::NotInstalled(name))
+            };
+
+            let mut dependents: Vec<(&InstalledMod, &Dependency)> = instance.mods().iter()
+                .flat_map(|x| x.dependencies.iter().map(move |dependency| (x, dependency)))
+                .filter(|(_, x)| x.mod_id == name)
+                .filter(|(_, x)| x.dependency_type == DependencyType::Require || (optional && x.dependency_type == DependencyType::Optional))
+                .collect();
+            dependents.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+
+            if dependents.is_empty() {
+                println!("No installed mods depend on \"{}\"", name);
+                return Ok(());
+            }
+
+            println!("Depend on {} {} ({}):", name.bright_yellow(), installed.to_string().bright_yellow(),
+                     dependents.len().to_string().bright_blue());
+            for (installed_mod, dependency) in dependents {
+                let requirement = if dependency.accepts(&installed) {
+                    dependency.to_string().normal()
+                } else {
+                    format!("{}, not met", dependency).bright_red()
+                };
+
+                println!("  {} {} ({})", installed_mod.name.bright_yellow(), installed_mod.version.to_string().bright_yellow(), requirement);
+            }
+        }
+        Commands::Repair { name: Some(name) } => {
+            let installed = match instance.mods().iter().find(|x| x.name == name) {
+                Some(installed) => installed,
+                None => return Err(CliError::NotInstalled(name))
+            };
+
+            let sha1 = match api(&instance, cache.as_ref(), &config, &pins).get_mod_unfiltered(&name) {
+                Ok(mod_info) => mod_info.releases.into_iter()
+                    .find(|x| x.version == installed.version)
+                    .and_then(|x| x.sha1),
+                Err(err) => return Err(CliError::Fetch(err))
+            };
+
+            if sha1.is_none() {
+                println!("{}", "The checksum of the installed release is unknown, it won't be verified.".bright_red());
+            }
+
+            let downloader = Downloader::new(&instance).with_source(source.clone());
+            let result = downloader.download_replacing(&name, &installed.version, sha1.as_deref(), &mut || {
+                instance.remove_mod(&name);
+                Ok(())
+            });
+
+            match result {
+                Ok(_) => println!("The mod \"{}\" was repaired", name),
+                Err(err) => return Err(CliError::Download { id: name, err })
+            }
+        }
+        Commands::Repair { name: None } => {
+            let broken_mods = instance.find_broken_mods();
+
+            if broken_mods.is_empty() {
+                println!("No broken mods found.");
+                return Ok(());
+            }
+
+            println!("Broken ({}):", broken_mods.len().to_string().bright_red());
+            for broken_mod in &broken_mods {
+                println!("  {} {}", broken_mod.name.bright_yellow(), broken_mod.version.to_string().bright_yellow());
+            }
+
+            if !confirm(config.confirm.repair, args.yes, broken_mods.len(), "Repair?") {
+                return Ok(());
+            }
+
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+            let downloader = Downloader::new(&instance).with_source(source.clone());
+            let mut failed = 0;
+
+            for broken_mod in broken_mods {
+                let sha1 = factorio_api.get_mod(&broken_mod.name).ok()
+                    .and_then(|x| x.releases.into_iter().find(|x| x.version == broken_mod.version))
+                    .and_then(|x| x.sha1);
+
+                // the broken folder is kept until the new archive is downloaded
+                let result = downloader.download_replacing(&broken_mod.name, &broken_mod.version, sha1.as_deref(),
+                                                           &mut || remove_dir_all(&broken_mod.path));
+
+                if let Err(err) = result {
+                    println!("Failed to repair \"{}\": {}", broken_mod.name, with_hint(err.to_string(), err.as_ref()));
+                    failed += 1;
+                }
+            }
+
+            println!("{}", "\nDone!".bright_green().bold());
+            check_failures(failed)?;
+        }
+        Commands::Paths => {
+            println!("Instance path: {} {}", instance.path.to_string_lossy().bright_yellow(), path_status(&instance.path));
+            println!("Mods path:     {} {}", instance.configured_mods_path.to_string_lossy().bright_yellow(),
+                     path_status(&instance.configured_mods_path));
+            if instance.mods_path != instance.configured_mods_path {
+                println!("  resolved to  {}", instance.mods_path.to_string_lossy().bright_yellow());
+            }
+
+            let config_path = config_path();
+            println!("Config path:   {} {}", config_path.to_string_lossy().bright_yellow(), path_status(&config_path));
+
+            let cache_path = config::cache_home();
+            println!("Cache path:    {} {}", cache_path.to_string_lossy().bright_yellow(), path_status(&cache_path));
+        }
+        Commands::Show { name, all_versions } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+
+            if let Some(reason) = invalid_id_message(&factorio_api, &name) {
+                return Err(CliError::InvalidModId { id: name, reason });
+            }
+
+            let mod_info = match factorio_api.get_mod_unfiltered(&name) {
+                Ok(val) => val,
+                Err(ureq::Error::StatusCode(404)) => {
+                    let reason = not_found_message(&factorio_api, &name);
+                    return Err(CliError::ModNotFound { id: name, reason });
+                }
+                Err(err) => return Err(CliError::Fetch(err))
+            };
+
+            let latest = mod_info.releases.iter().rev().find(|x| factorio_api.is_release_compatible(x));
+
+            println!("\
+Mod:        {}\n\
+Title:      {}\n\
+Summary:    {}\n\
+Author:     {}\n\
+Category:   {}\n\
+Tags:       {}\n\
+Downloads:  {}\n\
+Latest:     {}",
+                     name.bright_yellow(), mod_info.title.bright_yellow(), mod_info.summary,
+                     mod_info.owner.bright_yellow(), mod_info.category.bright_yellow(),
+                     mod_info.tags.join(", ").bright_yellow(), mod_info.downloads_count.to_string().bright_yellow(),
+                     match latest {
+                         Some(release) => release.version.to_string().bright_yellow(),
+                         None => "no compatible releases".bright_red()
+                     });
+
+            if let Some(release) = latest {
+                println!("Dependencies of {} ({}):", release.version.to_string().bright_yellow(),
+                         release.info_json.dependencies.len().to_string().bright_blue());
+                for dependency in &release.info_json.dependencies {
+                    println!("  {}", dependency.to_string().bright_yellow());
+                }
+            }
+
+            let releases: Vec<&ModRelease> = mod_info.releases.iter().rev()
+                .filter(|x| all_versions || factorio_api.is_release_compatible(x))
+                .collect();
+
+            println!("{} ({}):", if all_versions { "Releases" } else { "Compatible releases" },
+                     releases.len().to_string().bright_blue());
+            for release in releases {
+                if factorio_api.is_release_compatible(release) {
+                    println!("  {:<10} {}", release.version.to_string().bright_yellow(), release_date(&release.released_at));
+                } else {
+                    println!("  {:<10} {} {}", release.version.to_string().bright_yellow(), release_date(&release.released_at),
+                             format!("(incompatible, Factorio {})", release.info_json.factorio_version).bright_red());
+                }
+            }
+        }
+        Commands::Search { query, tag, compatible, limit } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+
+            let results = match factorio_api.search(&query, compatible) {
+                Ok(val) => val,
+                Err(err) => return Err(CliError::Fetch(err))
+            };
+
+            let results: Vec<ModSummary> = results.into_iter()
+                .filter(|x| match &tag {
+                    Some(tag) => x.category.eq_ignore_ascii_case(tag) || x.tags.iter().any(|x| x.eq_ignore_ascii_case(tag)),
+                    None => true
+                })
+                // the latest release may target another version, then look for an older compatible one
+                .filter(|x| !compatible || x.latest_release.as_ref().is_some_and(|x| factorio_api.is_release_compatible(x)) ||
+                    factorio_api.get_mod(&x.name).is_ok_and(|x| !x.releases.is_empty()))
+                .take(limit)
+                .collect();
+
+            println!("Found {} mods:", results.len().to_string().bright_blue());
+            for result in &results {
+                println!("  {} ({}) - {} downloads", result.name.bright_yellow(), result.title,
+                         result.downloads_count.to_string().bright_blue());
+                if !result.summary.is_empty() {
+                    println!("    {}", result.summary.lines().next().unwrap_or_default());
+                }
+            }
+        }
+        Commands::Stats => {
+            let mod_list = ModList::load(&instance).unwrap_or_default();
+            let disabled = instance.mods().iter().filter(|x| !mod_list.is_enabled(&x.name)).count();
+
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins);
+            let mut spinner = Spinner::new();
+
+            let mut outdated = 0;
+            let mut unknown = 0;
+            let mut categories: BTreeMap<String, usize> = BTreeMap::new();
+            let mut oldest: Option<(String, String)> = None;
+            let mut newest: Option<(String, String)> = None;
+
+            for installed_mod in instance.mods() {
+                spinner.update(format!("Fetching: {}", installed_mod.name));
+
+                let mod_info = match factorio_api.get_mod_short(&installed_mod.name) {
+                    Ok(mod_info) => mod_info,
+                    Err(_) => {
+                        unknown += 1;
+                        continue
+                    }
+                };
+
+                if !mod_info.category.is_empty() {
+                    *categories.entry(mod_info.category.clone()).or_insert(0) += 1;
+                }
+
+                let latest = mod_info.releases.iter().rev().find(|x| factorio_api.is_short_release_compatible(x));
+                if latest.is_some_and(|x| x.version > installed_mod.version) {
+                    outdated += 1;
+                }
+
+                if let Some(release) = mod_info.releases.iter().find(|x| x.version == installed_mod.version) {
+                    let date = release_date(&release.released_at).to_string();
+
+                    if oldest.as_ref().is_none_or(|x| date < x.1) {
+                        oldest = Some((installed_mod.name.clone(), date.clone()));
+                    }
+                    if newest.as_ref().is_none_or(|x| date > x.1) {
+                        newest = Some((installed_mod.name.clone(), date));
+                    }
+                }
+            }
+
+            spinner.finish();
+
+            println!("\
+Mods:           {}\n\
+Disabled:       {}\n\
+Outdated:       {}\n\
+Disk usage:     {}",
+                     instance.mods().len().to_string().bright_yellow(), disabled.to_string().bright_yellow(),
+                     outdated.to_string().bright_yellow(), format_size(instance.disk_usage()).bright_yellow());
+
+            if let Some((name, date)) = &oldest {
+                println!("Oldest release: {} {}", name.bright_yellow(), date);
+            }
+            if let Some((name, date)) = &newest {
+                println!("Newest release: {} {}", name.bright_yellow(), date);
+            }
+            if unknown != 0 {
+                println!("Not found on the portal: {}", unknown.to_string().bright_red());
+            }
+
+            println!("By category:");
+            for (category, count) in &categories {
+                println!("  {:<16} {}", category, count.to_string().bright_blue());
+            }
+        }
+        Commands::Compat { factorio_version } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins).targeting(factorio_version.clone());
+            let mut spinner = Spinner::new();
+
+            let mut compatible = Vec::new();
+            let mut blockers = Vec::new();
+
+            let names: Vec<String> = instance.mods().iter().map(|x| x.name.clone()).collect();
+            spinner.update(format!("Checking {} mods", names.len()));
+
+            for (installed_mod, mod_info) in instance.mods().iter().zip(factorio_api.get_mods(&names, jobs)) {
+                match mod_info {
+                    Ok(mod_info) => match mod_info.releases.last() {
+                        Some(release) => compatible.push((installed_mod, release.version.clone())),
+                        None => blockers.push((installed_mod, "no release".to_string()))
+                    },
+                    Err(err) => blockers.push((installed_mod, err.to_string()))
+                }
+            }
+
+            spinner.finish();
+
+            println!("Compatible with Factorio {} ({}):", factorio_version.to_string().bright_yellow(),
+                     compatible.len().to_string().bright_green());
+            for (installed_mod, version) in &compatible {
+                println!("  {} {} -> {}", installed_mod.name.bright_yellow(), installed_mod.version.to_string().bright_yellow(),
+                         version.to_string().bright_yellow());
+            }
+
+            println!("Incompatible ({}):", blockers.len().to_string().bright_red());
+            for (installed_mod, reason) in &blockers {
+                println!("  {} {} ({})", installed_mod.name.bright_yellow(), installed_mod.version.to_string().bright_yellow(), reason);
+            }
+        }
+        Commands::Update { names, mut exclude, prune, include_disabled, major_compat, format } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins)
+                .with_major_compat(major_compat);
+
+            // mods named explicitly are updated even if disabled
+            if !include_disabled {
+                let mod_list = ModList::load(&instance).unwrap_or_default();
+                let disabled: Vec<String> = instance.mods().iter()
+                    .filter(|x| !mod_list.is_enabled(&x.name) && !names.contains(&x.name))
+                    .map(|x| x.name.clone())
+                    .collect();
+
+                if !disabled.is_empty() && format == OutputFormat::Text {
+                    println!("Skipping {} disabled mods, use --include-disabled to update them",
+                             disabled.len().to_string().bright_blue());
+                }
+
+                exclude.extend(disabled);
+            }
+
+            let observer = PrintObserver::new();
+
+            let candidates: Vec<&InstalledMod> = instance.mods().iter()
+                .filter(|x| names.is_empty() || names.contains(&x.name))
+                .filter(|x| !exclude.contains(&x.name))
+                .collect();
+
+            let candidate_names: Vec<String> = candidates.iter().map(|x| x.name.clone()).collect();
+            if format == OutputFormat::Text {
+                observer.status(format!("Checking {} mods", candidate_names.len()));
+            }
+
+            let mut roots = Vec::new();
+            let mut failed = Vec::new();
+
+            for (installed_mod, mod_info) in candidates.iter().zip(factorio_api.get_mods(&candidate_names, jobs)) {
+                match mod_info.map(|x| x.releases.last().cloned()) {
+                    Ok(Some(release)) if release.version > installed_mod.version =>
+                        roots.push((installed_mod.name.clone(), release.version)),
+                    Ok(_) => {}
+                    Err(err) => failed.push((installed_mod.name.clone(), with_hint(format!("failed to fetch mod: {}", err), &err)))
+                }
+            }
+
+            if format != OutputFormat::Text {
+                let updates: Vec<AvailableUpdate> = candidates.iter()
+                    .filter_map(|installed| roots.iter().find(|x| x.0 == installed.name).map(|(name, latest)| AvailableUpdate {
+                        name: name.clone(),
+                        current: installed.version.clone(),
+                        latest: latest.clone()
+                    }))
+                    .collect();
+
+                if format == OutputFormat::Json {
+                    println!("{}", serde_json::to_string_pretty(&updates).unwrap());
+                } else {
+                    println!("{}", csv_row(&["name", "current", "latest"]));
+                    for update in updates {
+                        println!("{}", csv_row(&[update.name, update.current.to_string(), update.latest.to_string()]));
+                    }
+                }
+
+                // stdout is kept parseable
+                for (name, reason) in &failed {
+                    eprintln!("Failed to check {}: {}", name, reason);
+                }
+                return check_failures(failed.len());
+            }
+
+            let plan = pipeline::plan(&factorio_api, &instance, roots, &observer);
+            observer.finish();
+            warn_early_upgrade(&factorio_api, &instance);
+
+            failed.extend(plan.failed.into_iter()
+                .map(|(name, err)| (name, with_hint(format!("failed to process dependencies: {}", err), &err))));
+
+            let mut changes = plan.changes;
+            // excluded mods may still be required in a newer version by updated ones
+            changes.hold_updates_of(&exclude);
+
+            print_changes(&changes);
+            print_failures(&failed);
+
+            if changes.install.is_empty() && changes.update.is_empty() && changes.conflicts.is_empty() {
+                println!("Everything is up to date");
+                return check_failures(failed.len());
+            }
+
+            if !confirm_changes(config.confirm.download, args.yes, &changes) {
+                return Ok(());
+            }
+
+            let changed = apply_changes(&instance, &instance_name, &Downloader::new(&instance).with_source(source.clone()), changes, jobs, prune);
+            verify_installation(&instance);
+
+            let changed = changed?;
+            check_failures(failed.len())?;
+            run_post_install_hook(post_hook.as_deref(), &instance_name, &changed);
+        }
+        Commands::Migrate { factorio_version, apply } => {
+            let factorio_api = api(&instance, cache.as_ref(), &config, &pins).targeting(factorio_version.clone());
+            let observer = PrintObserver::new();
+
+            let mut roots = Vec::new();
+            let mut blockers = Vec::new();
+            let mut failed = Vec::new();
+
+            let names: Vec<String> = instance.mods().iter().map(|x| x.name.clone()).collect();
+            observer.status(format!("Checking {} mods", names.len()));
+
+            for (installed_mod, mod_info) in instance.mods().iter().zip(factorio_api.get_mods(&names, jobs)) {
+                match mod_info.map(|x| x.releases.last().cloned()) {
+                    Ok(Some(release)) => roots.push((installed_mod.name.clone(), release.version)),
+                    Ok(None) => blockers.push(installed_mod.name.clone()),
+                    Err(err) => failed.push((installed_mod.name.clone(), with_hint(format!("failed to fetch mod: {}", err), &err)))
+                }
+            }
+
+            let plan = pipeline::plan(&factorio_api, &instance, roots, &observer);
+            observer.finish();
+
+            let changes = plan.changes;
+            print_changes(&changes);
+
+            failed.extend(plan.failed.into_iter()
+                .map(|(name, err)| (name, with_hint(format!("failed to process dependencies: {}", err), &err))));
+            print_failures(&failed);
+
+            println!("No release for Factorio {} ({}):", factorio_version.to_string().bright_yellow(),
+                     blockers.len().to_string().bright_red());
+            for blocker in &blockers {
+                println!("  {}", blocker.bright_yellow());
+            }
+
+            // a mod which couldn't be checked may still block the migration
+            check_failures(failed.len())?;
+            if !apply {
+                return Ok(());
+            }
+
+            if !confirm_changes(config.confirm.download, args.yes, &changes) {
+                return Ok(());
+            }
+
+            let changed = apply_changes(&instance, &instance_name, &Downloader::new(&instance).with_source(source.clone()), changes, jobs, false);
+            verify_installation(&instance);
+
+            run_post_install_hook(post_hook.as_deref(), &instance_name, &changed?);
+        }
+        Commands::Check => {
+            let mut mod_list = match ModList::load(&instance) {
+                Ok(mod_list) => mod_list,
+                Err(err) => return Err(CliError::Io { path: mod_list_path(&instance), err })
+            };
+
+            let unlisted: Vec<&str> = instance.mods().iter()
+                .map(|x| x.name.as_str())
+                .filter(|x| !mod_list.contains(x))
+                .collect();
+
+            let missing: Vec<&str> = mod_list.mods.iter()
+                .map(|x| x.name.as_str())
+                .filter(|x| !is_mod_game_content(x) && !instance.mods().iter().any(|installed| &installed.name == x))
+                .collect();
+
+            if unlisted.is_empty() && missing.is_empty() {
+                println!("{}", "mod-list.json matches the installed mods".bright_green());
+                return Ok(());
+            }
+
+            if !missing.is_empty() {
+                println!("Listed in mod-list.json but not installed ({}):", missing.len().to_string().bright_red());
+                for name in &missing {
+                    println!("  {}", name.bright_yellow());
+                }
+            }
+
+            if unlisted.is_empty() {
+                return Ok(());
+            }
+
+            println!("Installed but not listed in mod-list.json ({}):", unlisted.len().to_string().bright_red());
+            for name in &unlisted {
+                println!("  {}", name.bright_yellow());
+            }
+
+            if !confirm(ConfirmMode::Always, args.yes, unlisted.len(), "Add them to mod-list.json as enabled?") {
+                return Ok(());
+            }
+
+            for name in &unlisted {
+                mod_list.add(name, true);
+            }
+
+            match mod_list.save(&instance) {
+                Ok(_) => println!("{}", "\nDone!".bright_green().bold()),
+                Err(err) => return Err(CliError::Io { path: mod_list_path(&instance), err })
+            }
+        }
+        Commands::Enable { names, dry_run } => set_mods_enabled(&instance, &names, true, dry_run)?,
+        Commands::Disable { names, dry_run } => set_mods_enabled(&instance, &names, false, dry_run)?,
+        Commands::Undo => {
+            let entries = history::load();
+            let operation = history::last_operation(&entries, &instance_name);
+
+            if operation.is_empty() {
+                println!("Nothing to undo");
+                return Ok(());
+            }
+
+            println!("Revert ({}):", operation.len().to_string().bright_blue());
+            for entry in &operation {
+                let line = match entry.action {
+                    Action::Install => format!("remove {}", entry.mod_id.bright_yellow()),
+                    Action::Update => format!("downgrade {} to {}", entry.mod_id.bright_yellow(),
+                                              display_version(&entry.old_version).bright_yellow()),
+                    Action::Remove => format!("reinstall {} {}", entry.mod_id.bright_yellow(),
+                                              display_version(&entry.old_version).bright_yellow()),
+                };
+                println!("  {}", line);
+            }
+
+            if !confirm(ConfirmMode::Always, args.yes, operation.len(), "Proceed?") {
+                return Ok(());
+            }
+
+            let downloader = Downloader::new(&instance).with_source(source.clone());
+            let mut failed = 0;
+
+            for entry in operation {
+                let reverted = match (entry.action, &entry.old_version) {
+                    (Action::Install, _) => {
+                        instance.remove_mod(&entry.mod_id);
+                        HistoryEntry::new(&instance_name, Action::Remove, &entry.mod_id, entry.new_version.clone(), None)
+                    }
+                    (Action::Update, Some(old_version)) | (Action::Remove, Some(old_version)) => {
+                        // the current version stays installed if the old one can't be fetched
+                        let result = downloader.download_replacing(&entry.mod_id, old_version, None, &mut || {
+                            instance.remove_mod(&entry.mod_id);
+                            Ok(())
+                        });
+
+                        if let Err(err) = result {
+                            println!("Failed to revert {} of {}: {}", entry.action, entry.mod_id,
+                                     with_hint(err.to_string(), err.as_ref()));
+                            failed += 1;
+                            continue
+                        }
+
+                        let action = if entry.action == Action::Update { Action::Update } else { Action::Install };
+                        HistoryEntry::new(&instance_name, action, &entry.mod_id, entry.new_version.clone(), Some(old_version.clone()))
+                    }
+                    _ => {
+                        println!("Can't revert {} of {}: the previous version is unknown", entry.action, entry.mod_id);
+                        continue
+                    }
+                };
+
+                _ = history::record(&reverted.undoing(entry.operation));
+            }
+
+            println!("{}", "\nDone!".bright_green().bold());
+            check_failures(failed)?;
+        }
+        Commands::Order => {
+            let load_order = compute_load_order(&instance);
+
+            println!("Load order ({}):", load_order.order.len().to_string().bright_blue());
+            for (index, id) in load_order.order.iter().enumerate() {
+                println!("  {:>3}. {}", index + 1, id.bright_yellow());
+            }
+
+            if !load_order.cycle.is_empty() {
+                println!("Dependency cycle ({}):", load_order.cycle.len().to_string().bright_red());
+                for id in &load_order.cycle {
+                    println!("  {}", id.bright_yellow());
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Enables or disables the installed mods matching `names` in mod-list.json, printing the changes.
+/// With `dry_run` the file isn't written.
+fn set_mods_enabled(instance: &Instance, names: &[String], enable: bool, dry_run: bool) -> Result<(), CliError> {
+    let mut selected: Vec<String> = Vec::new();
+    for name in names {
+        let matched: Vec<String> = instance.mods().iter()
+            .filter(|x| if is_glob(name) { glob_match(name, &x.name) } else { &x.name == name })
+            .map(|x| x.name.clone())
+            .collect();
+
+        if matched.is_empty() {
+            return Err(CliError::NotInstalled(name.clone()));
+        }
+        selected.extend(matched);
+    }
+    selected.sort();
+    selected.dedup();
+
+    let old = match ModList::load(instance) {
+        Ok(mod_list) => mod_list,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => ModList::default(),
+        Err(err) => return Err(CliError::Io { path: mod_list_path(instance), err })
+    };
+
+    let mut new = old.clone();
+    for name in &selected {
+        new.set_enabled(name, enable);
+    }
+
+    let diff = old.diff(&new);
+    if diff.is_empty() {
+        println!("mod-list.json is already up to date");
+        return Ok(());
+    }
+    print_mod_list_diff(&diff);
+
+    if !enable {
+        // mods which stay enabled but won't load without the disabled ones
+        for name in &selected {
+            for (installed, dependency) in required_by(instance.mods(), name) {
+                if new.is_enabled(&installed.name) {
+                    println!("{} {} requires {} ({})", "Warning:".bright_yellow(), installed.name.bright_yellow(),
+                             name.bright_yellow(), dependency);
+                }
+            }
+        }
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    match new.save(instance) {
+        Ok(_) => println!("{}", "Done!".bright_green().bold()),
+        Err(err) => return Err(CliError::Io { path: mod_list_path(instance), err })
+    }
+
+    Ok(())
+}
+
+fn print_mod_list_diff(diff: &ModListDiff) {
+    let state = |enabled: bool| if enabled { "enabled".bright_green() } else { "disabled".bright_red() };
+
+    println!("Changes of mod-list.json:");
+    for (name, enabled) in &diff.added {
+        println!("  {} {} ({})", "+".bright_green(), name.bright_yellow(), state(*enabled));
+    }
+    for name in &diff.removed {
+        println!("  {} {}", "-".bright_red(), name.bright_yellow());
+    }
+    for (name, enabled) in &diff.toggled {
+        println!("  {} {} -> {}", name.bright_yellow(), state(!enabled), state(*enabled));
+    }
+}
+
+/// Prints the installed mods, only the ones matching `pattern` if given.
+fn list_mods(instance: &Instance, pattern: Option<&str>, format: OutputFormat) {
+    let mods: Vec<&InstalledMod> = instance.mods().iter()
+        .filter(|x| pattern.is_none_or(|pattern| glob_match(pattern, &x.name)))
+        .collect();
+
+    if format != OutputFormat::Text {
+        let mod_list = ModList::load(instance).unwrap_or_default();
+        let listed: Vec<ListedMod> = mods.iter()
+            .map(|x| ListedMod {
+                name: x.name.clone(),
+                version: x.version.clone(),
+                enabled: mod_list.is_enabled(&x.name),
+                size: instance.mod_size(&x.name, &x.version)
+            })
+            .collect();
+
+        if format == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&listed).unwrap());
+        } else {
+            println!("{}", csv_row(&["name", "version", "enabled", "size"]));
+            for listed_mod in listed {
+                println!("{}", csv_row(&[listed_mod.name, listed_mod.version.to_string(), listed_mod.enabled.to_string(), listed_mod.size.to_string()]));
+            }
+        }
+        return;
+    }
+
+    match pattern {
+        Some(pattern) => println!("Installed {} mods matching \"{}\":", mods.len().to_string().bright_blue(), pattern),
+        None => println!("Installed {} mods:", mods.len().to_string().bright_blue())
+    }
+    for mod_info in mods {
+        println!("  {} {}", mod_info.name.bright_yellow(), mod_info.version.to_string().bright_yellow());
+    }
+
+    let broken_mods = instance.find_broken_mods();
+    if !broken_mods.is_empty() {
+        println!("{}", format!("Skipped {} mods with unreadable info.json, see `fmods repair`", broken_mods.len()).bright_red());
+    }
+}
+
+fn print_changes(changes: &Changes) {
+    println!("Install ({}):", changes.install.len().to_string().bright_green());
+    for install in &changes.install {
+        println!("  {} {}", install.id.to_string().bright_yellow(), install.version.to_string().bright_yellow());
+    }
+
+    println!("Update ({}):", changes.update.len().to_string().bright_yellow());
+    for update in &changes.update {
+        println!("  {} {} -> {}", update.id.to_string().bright_yellow(), update.old_version.to_string().bright_yellow(),
+            update.new_version.to_string().bright_yellow()
+        );
+    }
+
+    println!("Conflicts ({}):", changes.conflicts.len().to_string().bright_red());
+    for conflict in &changes.conflicts {
+        println!("  {}", conflict.bright_yellow());
+    }
+
+    if !changes.held.is_empty() {
+        println!("Not updated, requirements stay unmet ({}):", changes.held.len().to_string().bright_red());
+        for held in &changes.held {
+            println!("  {} {} (requires {})", held.id.bright_yellow(), held.old_version.to_string().bright_yellow(),
+                held.new_version.to_string().bright_yellow()
+            );
+        }
+    }
+
+    if !changes.optional.is_empty() {
+        println!("Optional not installed ({}):", changes.optional.len().to_string().bright_blue());
+        for optional in &changes.optional {
+            println!("  {}", optional.bright_yellow());
+        }
+    }
+}
+
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Most popular mod ids which contain `name` or differ from it by a few chars.
+fn suggest_mods(mods: Vec<ModSummary>, name: &str) -> Vec<String> {
+    let query = name.to_lowercase();
+    let max_distance = (query.chars().count() / 3).max(2);
+
+    let mut candidates: Vec<(usize, String)> = mods.into_iter()
+        .filter_map(|x| {
+            let id = x.name.to_lowercase();
+            let distance = if id.contains(&query) {
+                id.chars().count() - query.chars().count()
+            } else {
+                edit_distance(&id, &query)
+            };
+
+            (id.contains(&query) || distance <= max_distance).then_some((distance, x.name))
+        })
+        .collect();
+
+    // stable, so equally close mods stay ordered by downloads
+    candidates.sort_by_key(|x| x.0);
+
+    candidates.into_iter().take(MAX_SUGGESTIONS).map(|x| x.1).collect()
+}
+
+/// Hint which mods the user may have meant by `name`. A mod whose id differs only in case or whose title
+/// is `name` is suggested alone, since ids are case-sensitive and often differ from the titles.
+fn did_you_mean(factorio_api: &FactorioApi, name: &str) -> Option<String> {
+    let mods = factorio_api.search("", false).ok()?;
+
+    let query = name.trim().to_lowercase();
+    let exact: Vec<&ModSummary> = mods.iter()
+        .filter(|x| x.name.to_lowercase() == query || x.title.to_lowercase() == query)
+        .collect();
+
+    if let [exact] = exact.as_slice() {
+        return Some(format!("the id is \"{}\"", exact.name));
+    }
+
+    let suggestions = suggest_mods(mods, name);
+
+    (!suggestions.is_empty()).then(|| format!("did you mean: {}?", suggestions.join(", ")))
+}
+
+fn not_found_message(factorio_api: &FactorioApi, name: &str) -> String {
+    match did_you_mean(factorio_api, name) {
+        Some(hint) => format!("not found, {}", hint),
+        None => "not found".to_string()
+    }
+}
+
+/// Fetches the mod to resolve dependencies from, failing if it has no compatible releases.
+fn get_root_mod(factorio_api: &FactorioApi, name: String) -> Result<ModInfo, CliError> {
+    if let Some(reason) = invalid_id_message(factorio_api, &name) {
+        return Err(CliError::InvalidModId { id: name, reason });
+    }
+
+    let mod_info = match factorio_api.get_mod(&name) {
+        Ok(val) => val,
+        Err(ureq::Error::StatusCode(404)) => {
+            let reason = not_found_message(factorio_api, &name);
+            return Err(CliError::ModNotFound { id: name, reason });
+        }
+        Err(err) => return Err(CliError::Fetch(err))
+    };
+
+    if mod_info.releases.is_empty() {
+        return Err(CliError::NoSuitableRelease(name));
+    }
+
+    Ok(mod_info)
+}
+
+/// Rejects `name` locally if it can't be a mod id, explaining why.
+fn invalid_id_message(factorio_api: &FactorioApi, name: &str) -> Option<String> {
+    let err = validate_mod_id(name).err()?;
+
+    Some(match did_you_mean(factorio_api, name) {
+        Some(hint) => format!("invalid id: {}, {}", err, hint),
+        None => format!("invalid id: {}", err)
+    })
+}
+
+/// Instance selected for the shell session by `FMODS_INSTANCE`, between `--instance` and the default instance
+fn instance_from_env() -> Option<String> {
+    std::env::var("FMODS_INSTANCE").ok().filter(|x| !x.is_empty())
+}
+
+fn open_instance(config: &Config, name: &str) -> Result<Instance, CliError> {
+    match config.open_instance(name) {
+        None => Err(instance_not_found(config, name)),
+        Some(Ok(instance)) => Ok(instance),
+        Some(Err(err)) => Err(CliError::OpenInstance { name: name.to_string(), err })
+    }
+}
+
+/// Portal client for `instance` with the cache, the renames and the pins of the config
+fn api<'a>(instance: &'a Instance, cache: Option<&'a Cache>, config: &'a Config, pins: &'a HashMap<String, VersionRange>) -> FactorioApi<'a> {
+    FactorioApi::new(instance).cached(cache).with_renames(&config.renames).with_pins(pins)
+}
+
+/// Where to download mods for `instance` from, the official portal if requested by the flag or the config
+fn download_source(config: &Config, official_portal: bool, instance: &Instance) -> Source {
+    if official_portal || config.official_portal {
+        Source::Portal(config.credentials(instance))
+    } else {
+        Source::Mirrors(config.download_mirrors.clone())
+    }
+}
+
+fn instance_not_found(config: &Config, name: &str) -> CliError {
+    let closest = config.instances.keys()
+        .map(|x| (edit_distance(&x.to_lowercase(), &name.to_lowercase()), x))
+        .filter(|x| x.0 <= (name.chars().count() / 3).max(2))
+        .min()
+        .map(|x| x.1.clone());
+
+    let mut configured: Vec<String> = config.instances.keys().cloned().collect();
+    configured.sort();
+
+    CliError::InstanceNotFound { name: name.to_string(), closest, configured }
+}
+
+fn print_failures(failed: &[(String, String)]) {
+    if failed.is_empty() {
+        return;
+    }
+
+    println!("Failed ({}):", failed.len().to_string().bright_red());
+    for (name, reason) in failed {
+        println!("  {} ({})", name.bright_yellow(), reason);
+    }
+}
+
+/// Splits `download` arguments into mod ids with optional versions.
+///
+/// Besides `name@version`, the old form `<name> <version>` is accepted when exactly two values are given
+/// and the second one is a version.
+fn parse_download_targets(values: Vec<String>) -> Result<Vec<(String, Option<VersionRequest>)>, ParseIntError> {
+    if let [name, version] = values.as_slice() {
+        if !name.contains('@') {
+            if let Ok(version) = VersionRequest::from_str(version) {
+                return Ok(vec![(name.trim().to_string(), Some(version))]);
+            }
+        }
+    }
+
+    values.into_iter().map(parse_download_target).collect()
+}
+
+/// `name` or `name@version`
+fn parse_download_target(value: String) -> Result<(String, Option<VersionRequest>), ParseIntError> {
+    match value.split_once('@') {
+        Some((name, version)) => Ok((name.trim().to_string(), Some(VersionRequest::from_str(version)?))),
+        None => Ok((value.trim().to_string(), None))
+    }
+}
+
+/// Lines of the file, or stdin for `-`, without blank lines and `#` comments.
+fn read_mods_file(path: &Path) -> Result<Vec<String>, CliError> {
+    let content = if path == Path::new("-") {
+        io::read_to_string(stdin())
+    } else {
+        std::fs::read_to_string(path)
+    }.map_err(|err| CliError::Io { path: path.to_path_buf(), err })?;
+
+    Ok(content.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+fn unknown_key_message(key: &config::UnknownKey) -> String {
+    match &key.suggestion {
+        Some(suggestion) => format!("Unknown key \"{}\" is ignored, did you mean \"{}\"?", key.key.bright_yellow(), suggestion.bright_green()),
+        None => format!("Unknown key \"{}\" is ignored", key.key.bright_yellow())
+    }
+}
+
+/// Warns about keys of the config file which have no effect, written to stderr to keep the output of commands clean.
+fn warn_unknown_config_keys() {
+    let keys = std::fs::read_to_string(config_path()).ok().and_then(|x| config::unknown_keys(&x).ok()).unwrap_or_default();
+
+    for key in keys {
+        eprintln!("{} {}", "Warning:".bright_yellow(), unknown_key_message(&key));
+    }
+}
+
+/// Warns about required dependencies of the releases in `declared` which are neither installed
+/// in a suitable version nor among the `roots` being installed.
+fn warn_missing_dependencies(instance: &Instance, roots: &[(String, Version)], declared: &[(String, Vec<Dependency>)]) {
+    for (name, dependencies) in declared {
+        for dependency in dependencies {
+            if dependency.dependency_type != DependencyType::Require || is_mod_game_content(&dependency.mod_id) {
+                continue
+            }
+
+            let met = roots.iter().any(|(id, version)| id == &dependency.mod_id && dependency.accepts(version))
+                || instance.mods().iter().any(|x| x.name == dependency.mod_id && dependency.accepts(&x.version));
+
+            if !met {
+                println!("{} {} requires {} which won't be installed", "Warning:".bright_yellow(), name.bright_yellow(), dependency);
+            }
+        }
+    }
+}
+
+/// Warns if most of the fetched mods only have releases for older Factorio versions than the instance.
+fn warn_early_upgrade(factorio_api: &FactorioApi, instance: &Instance) {
+    if let Some(target) = factorio_api.outdated_target() {
+        println!("{} your instance is {} but most mods only have releases for {}, you may have upgraded too early",
+                 "Warning:".bright_yellow(), PartialVersion::major_minor(&instance.version),
+                 PartialVersion::major_minor(&target));
+    }
+}
+
+/// Prints the total size of the planned downloads, `TooLarge` if it's over `max`.
+/// Archives whose size the server doesn't report are not counted.
+fn check_download_size(downloader: &Downloader, changes: &Changes, max: u64, jobs: usize) -> Result<(), CliError> {
+    let targets: Vec<(String, Version)> = changes.install.iter().map(|x| (x.id.clone(), x.version.clone()))
+        .chain(changes.update.iter().map(|x| (x.id.clone(), x.new_version.clone())))
+        .collect();
+
+    let (total, unknown) = downloader.total_size(&targets, jobs);
+
+    print!("Download size: {}", format_size(total).bright_yellow());
+    if unknown > 0 {
+        print!(" (unknown for {} mods)", unknown);
+    }
+    println!();
+
+    if total > max {
+        return Err(CliError::TooLarge { total, max });
+    }
+
+    Ok(())
+}
+
+/// Downloads, updates and removes mods according to `changes` with up to `jobs` parallel downloads,
+/// stops after a step with a failed download. Every performed operation is recorded to the history.
+/// With `prune` other versions of the installed and updated mods are removed afterwards.
+///
+/// Returns the ids of the changed mods, `CliError::Failed` if some download failed.
+fn apply_changes(instance: &Instance, instance_name: &str, downloader: &Downloader, changes: Changes,
+                 jobs: usize, prune: bool) -> Result<Vec<String>, CliError> {
+    let applied = pipeline::apply(instance, instance_name, downloader, changes, jobs, prune, &PrintObserver::new());
+    check_failures(applied.failed.len())?;
+
+    println!("{}", "\nDone!".bright_green().bold());
+
+    Ok(applied.changed)
+}
+
+/// Warns about installed mods whose dependencies differ from the ones used for resolving.
+fn print_mismatches(mismatches: &[Mismatch]) {
+    for mismatch in mismatches {
+        let dependencies: Vec<String> = mismatch.added.iter().map(|x| format!("+ {}", x).bright_green().to_string())
+            .chain(mismatch.missing.iter().map(|x| format!("- {}", x).bright_red().to_string()))
+            .collect();
+
+        println!("{} {} {} declares other dependencies than the mod portal reported: {}", "Warning:".bright_yellow(),
+                 mismatch.id.bright_yellow(), mismatch.version, dependencies.join(", "));
+    }
+
+    if let Some(first) = mismatches.first() {
+        println!("Run {} again to resolve them", format!("fmods download {}", first.id).bright_yellow());
+    }
+}
+
+/// Runs the post-install command through the shell, passing the instance name and the changed mods
+/// (comma separated) in `FMODS_INSTANCE` and `FMODS_CHANGED_MODS`.
+fn run_post_install_hook(command: Option<&str>, instance_name: &str, changed: &[String]) {
+    let command = match command {
+        Some(command) if !changed.is_empty() => command,
+        _ => return
+    };
+
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+
+    println!("Running post-install command...");
+    let status = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .env("FMODS_INSTANCE", instance_name)
+        .env("FMODS_CHANGED_MODS", changed.join(","))
+        .status();
+
+    match status {
+        Ok(status) if status.success() => println!("{}", "Post-install command succeeded".bright_green()),
+        Ok(status) => println!("{}", format!("Post-install command failed: {}", status).bright_red()),
+        Err(err) => println!("{}", format!("Failed to run post-install command: {}", err).bright_red())
+    }
+}
+
+/// Re-reads the mods directory and warns about installed mods whose requirements aren't met,
+/// e.g. because some download failed.
+fn verify_installation(instance: &Instance) {
+    let instance = match instance.reload() {
+        Ok(instance) => instance,
+        Err(err) => return println!("Failed to verify the installation: {}", err)
+    };
+
+    let unmet = unmet_requirements(&instance);
+    if unmet.is_empty() {
+        return;
+    }
+
+    println!("{} ({}):", "Unmet requirements".bright_red(), unmet.len().to_string().bright_red());
+    for (name, dependency) in &unmet {
+        println!("  {} requires {}", name.bright_yellow(), dependency.to_string().bright_yellow());
+    }
+}
+
+/// Asks to select one of the newest `limit` releases by its number, or any release by its version.
+fn pick_version(releases: &[ModRelease], limit: usize) -> VersionRequest {
+    let shown: Vec<&ModRelease> = releases.iter().rev().take(limit).collect();
+
+    println!("Select version by number or type it ({} for the newest):", "latest".bold());
+    for (index, release) in shown.iter().enumerate() {
+        println!("  {:>2}. {}", index + 1, release.version.to_string().bright_yellow());
+    }
+    if releases.len() > limit {
+        println!("  ... and {} older", (releases.len() - limit).to_string().bright_blue());
+    }
+
+    loop {
+        let mut input = String::new();
+        stdin().read_line(&mut input).unwrap();
+        let input = input.trim();
+
+        // versions always contain a dot, so a plain number is an index
+        if let Ok(index) = input.parse::<usize>() {
+            match index.checked_sub(1).and_then(|x| shown.get(x)) {
+                Some(release) => return VersionRequest::Exact(release.version.clone()),
+                None => {
+                    println!("There is no release with the number {}.", index);
+                    continue
+                }
+            }
+        }
+
+        match VersionRequest::from_str(input) {
+            Ok(version) => return version,
+            Err(err) => println!("Failed to parse version: {}", err)
+        }
+    }
+}
+
+fn display_version(version: &Option<Version>) -> String {
+    version.as_ref().map(|x| x.to_string()).unwrap_or_else(|| "?".to_string())
+}
+
+fn release_date(released_at: &str) -> &str {
+    released_at.get(..10).unwrap_or(released_at)
+}
+
+/// Prints the diagnostics asked for in bug reports as plain text, to be pasted into an issue.
+fn selfcheck(config: &Config, timeout: u64, jobs: usize, cache: bool, official_portal: bool) {
+    println!("fmods {}", env!("CARGO_PKG_VERSION"));
+    println!("OS: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+
+    println!("\nPaths:");
+    for (name, path) in [("home", config::home_dir()), ("config", config_path()), ("cache", config::cache_home()), ("history", history::history_path())] {
+        println!("  {:<8} {} {}", format!("{}:", name), path.display(), path_status(&path).clear());
+    }
+
+    let mut names: Vec<&String> = config.instances.keys().collect();
+    names.sort();
+
+    println!("\nInstances ({}, default: {}):", names.len(), config.default_instance.as_deref().unwrap_or("none"));
+    for name in names {
+        let path = &config.instances[name];
+        match config.open_instance(name).unwrap() {
+            Ok(instance) => {
+                let version = if instance.has_factorio_version() {
+                    format!("Factorio {}", instance.version)
+                } else {
+                    "Factorio version unknown".to_string()
+                };
+
+                println!("  {}: {} {}, {}, {} mods", name, path.display(), path_status(path).clear(), version, instance.mods().len());
+                println!("    mods: {} {}", instance.mods_path.display(), path_status(&instance.mods_path).clear());
+            }
+            Err(err) => println!("  {}: {} {}, broken: {}", name, path.display(), path_status(path).clear(), err)
+        }
+    }
+
+    println!("\nSettings:");
+    println!("  timeout:  {} s", timeout);
+    println!("  jobs:     {}", jobs);
+    println!("  cache:    {}", if cache { format!("{} s", config.cache_ttl) } else { "disabled".to_string() });
+    println!("  source:   {}", if official_portal { "official portal" } else { "mirrors" });
+    println!("  mirrors:  {}", config.download_mirrors.join(", "));
+    println!("  renames:  {}, pins: {}", config.renames.len(), config.pins.len());
+
+    println!("\nNetwork:");
+    let mut hosts = vec!["https://mods.factorio.com/api/mods?page_size=1".to_string()];
+    hosts.extend(config.download_mirrors.iter().filter_map(|x| url::Url::parse(x).ok()).map(|x| x.origin().ascii_serialization()));
+
+    for url in hosts {
+        let started = Instant::now();
+        // any answer of the server means it's reachable
+        let status = match http::agent().get(&url).call() {
+            Ok(response) => format!("ok, HTTP {}", response.status().as_u16()),
+            Err(ureq::Error::StatusCode(code)) => format!("ok, HTTP {}", code),
+            Err(err) => with_hint(format!("failed: {}", err), &err)
+        };
+
+        println!("  {}: {} ({} ms)", url, status, started.elapsed().as_millis());
+    }
+}
+
+/// Whether the path exists and can be written. The permission bits don't tell it for the current user,
+/// so a file is opened for appending and a directory gets a probe file created and removed right away.
+fn path_status(path: &Path) -> ColoredString {
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return "(doesn't exist)".bright_red(),
+    };
+
+    let probe = if metadata.is_dir() {
+        let probe_path = path.join(format!(".fmods-write-test.{}", std::process::id()));
+        OpenOptions::new().write(true).create_new(true).open(&probe_path).map(|_| {
+            _ = std::fs::remove_file(&probe_path);
+        })
+    } else {
+        OpenOptions::new().append(true).open(path).map(|_| ())
+    };
+
+    match probe {
+        Err(err) if matches!(err.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem) => "(read-only)".bright_red(),
+        _ => "(ok)".bright_green(),
+    }
+}
+
+fn installed_mod_info(instance: &Instance, installed_mod: &InstalledMod) {
+    println!("\
+Mod:              {}\n\
+Version:          {}",
+             installed_mod.name.bright_yellow(), installed_mod.version.to_string().bright_yellow());
+
+    match minimum_factorio_version(&installed_mod.dependencies) {
+        Some(version) => {
+            let verdict = if &instance.version >= version {
+                format!("compatible with {}", instance.version).bright_green()
+            } else {
+                format!("incompatible with {}", instance.version).bright_red()
+            };
+
+            println!("Minimum Factorio: {} ({})", version.to_string().bright_yellow(), verdict);
+        }
+        None => println!("Minimum Factorio: {}", "not declared".bright_yellow())
+    }
+
+    println!("Dependencies:");
+    for dependency in &installed_mod.dependencies {
+        println!("  {}", dependency);
+    }
 }
 
 fn instance_info(instance: &Instance, instance_name: &String) {
     println!("\
 Instance:       {}\n\
 Path:           {}\n\
+Mods path:      {}\n\
 Version:        {}\n\
 Mods installed: {}\n\
 Game content versions:",
              instance_name.bright_yellow(), instance.path.to_string_lossy().bright_yellow(),
-             instance.version.to_string().bright_yellow(), instance.mods.len().to_string().bright_yellow());
+             instance.configured_mods_path.to_string_lossy().bright_yellow(),
+             instance.version.to_string().bright_yellow(), instance.mods().len().to_string().bright_yellow());
 
     for game_content_version in &instance.game_content_versions {
         println!("  {} {}", game_content_version.0, game_content_version.1.to_string().bright_yellow())
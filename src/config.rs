@@ -1,49 +1,512 @@
-use dirs::config_dir;
+use crate::downloader::{Credentials, DEFAULT_MIRROR};
+use crate::instance;
+use crate::instance::Instance;
+use crate::mod_info::{Version, VersionRange};
+use crate::utils::{edit_distance, parse_size};
+use dirs::{cache_dir, config_dir};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs::OpenOptions;
+use std::collections::{BTreeMap, HashMap};
+use std::env::var_os;
+use std::fmt::{Display, Formatter};
+use std::fs::{rename, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::str::FromStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
     pub ask: bool,
     pub default_instance: Option<String>,
+    /// Seconds for which fetched mod info is reused
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+    /// Seconds a request, including a whole download, may take
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Shell command run after mods were installed or updated
+    #[serde(default)]
+    pub post_install_command: Option<String>,
+    /// Download mods from mods.factorio.com instead of the mirrors
+    #[serde(default)]
+    pub official_portal: bool,
+    /// URL templates of the mirrors with `{id}` and `{version}`, tried in order
+    #[serde(default = "default_download_mirrors")]
+    pub download_mirrors: Vec<String>,
+    /// Account for the official portal, read from the game's `player-data.json` if not set
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Upper limit of parallel requests and downloads
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+    /// Largest total download of one operation, like `500MiB`
+    #[serde(default)]
+    pub max_download: Option<String>,
     pub instances: HashMap<String, PathBuf>,
+    /// Instances which are only a mods directory, with the Factorio version they are used with
+    #[serde(default)]
+    pub mods_only: HashMap<String, Version>,
+    #[serde(default)]
+    pub confirm: Confirm,
+    /// Old mod ids mapped to the new ones, for mods renamed on the portal
+    #[serde(default)]
+    pub renames: HashMap<String, String>,
+    /// Version ranges like `>=1.0, <2.0` the mods are kept within, by the mod id
+    #[serde(default)]
+    pub pins: HashMap<String, String>,
 }
 
+fn default_cache_ttl() -> u64 {
+    60 * 60
+}
+
+fn default_timeout() -> u64 {
+    300
+}
+
+fn default_download_mirrors() -> Vec<String> {
+    vec![DEFAULT_MIRROR.to_string()]
+}
+
+fn default_jobs() -> usize {
+    4
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmMode {
+    Always,
+    Never,
+    /// Ask only if more than the given number of mods is affected
+    Threshold(usize),
+}
+
+impl FromStr for ConfirmMode {
+    type Err = ();
+
+    /// `always`, `never` or the threshold
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(ConfirmMode::Always),
+            "never" => Ok(ConfirmMode::Never),
+            _ => s.parse().map(ConfirmMode::Threshold).map_err(|_| ())
+        }
+    }
+}
+
+impl Display for ConfirmMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfirmMode::Always => f.write_str("always"),
+            ConfirmMode::Never => f.write_str("never"),
+            ConfirmMode::Threshold(threshold) => write!(f, "{}", threshold)
+        }
+    }
+}
+
+/// Confirmation behavior for each kind of operation
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Confirm {
+    pub download: ConfirmMode,
+    pub remove: ConfirmMode,
+    pub repair: ConfirmMode,
+}
+
+impl Default for Confirm {
+    fn default() -> Self {
+        Confirm {
+            download: ConfirmMode::Always,
+            remove: ConfirmMode::Never,
+            repair: ConfirmMode::Always,
+        }
+    }
+}
+
+
+/// Keys accepted by `Config::get` and `Config::set`
+pub const KEYS: [&str; 10] = [
+    "ask", "default_instance", "cache_ttl", "timeout", "jobs", "max_download", "official_portal", "confirm.download", "confirm.remove", "confirm.repair"
+];
+
+/// Fields of the config file, for reporting the ones serde ignores
+const FIELDS: [&str; 16] = [
+    "ask", "default_instance", "cache_ttl", "timeout", "post_install_command", "official_portal", "download_mirrors",
+    "username", "token", "jobs", "max_download", "instances", "mods_only", "confirm", "renames", "pins"
+];
+
+const CONFIRM_FIELDS: [&str; 3] = ["download", "remove", "repair"];
+
+/// Key of the config file which is ignored when loading it, probably a misspelled one
+#[derive(Debug, Eq, PartialEq)]
+pub struct UnknownKey {
+    /// Dotted path of the key, like `confirm.downlaod`
+    pub key: String,
+    /// The known key it's closest to, if it's close enough to be a typo
+    pub suggestion: Option<String>
+}
+
+fn unknown_key(prefix: &str, key: &str, known: &[&str]) -> UnknownKey {
+    let suggestion = known.iter()
+        .map(|x| (edit_distance(key, x), x))
+        .filter(|(distance, x)| *distance <= 2.max(x.len() / 4))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, x)| format!("{}{}", prefix, x));
+
+    UnknownKey { key: format!("{}{}", prefix, key), suggestion }
+}
+
+/// Finds the keys of the config text which `Config::load` ignores.
+pub fn unknown_keys(text: &str) -> Result<Vec<UnknownKey>, Error> {
+    let table: toml::Table = toml::from_str(text).map_err(|err| Error::Parse(err.to_string()))?;
+    let mut result = Vec::new();
+
+    for (key, value) in &table {
+        if !FIELDS.contains(&key.as_str()) {
+            result.push(unknown_key("", key, &FIELDS));
+        } else if let ("confirm", Some(confirm)) = (key.as_str(), value.as_table()) {
+            result.extend(confirm.keys()
+                .filter(|x| !CONFIRM_FIELDS.contains(&x.as_str()))
+                .map(|x| unknown_key("confirm.", x, &CONFIRM_FIELDS)));
+        }
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownKey(String),
+    InvalidValue { key: String, expected: &'static str },
+    /// The config file is not valid TOML
+    Parse(String),
+    /// The config file exists but can't be read
+    Read(String)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownKey(key) => write!(f, "Unknown key \"{}\", valid keys: {}", key, KEYS.join(", ")),
+            Error::InvalidValue { key, expected } => write!(f, "Invalid value of \"{}\", expected {}", key, expected),
+            Error::Parse(err) => write!(f, "Invalid config file: {}", err),
+            Error::Read(err) => write!(f, "Failed to read the config file: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn parse<T: FromStr>(key: &str, value: &str, expected: &'static str) -> Result<T, Error> {
+    value.parse().map_err(|_| Error::InvalidValue { key: key.to_string(), expected })
+}
+
+static HOME: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keeps all files of fmods under `path`, takes precedence over `FMODS_HOME`.
+pub fn set_home(path: PathBuf) {
+    _ = HOME.set(path);
+}
+
+fn custom_home() -> Option<PathBuf> {
+    HOME.get().cloned().or_else(|| var_os("FMODS_HOME").filter(|x| !x.is_empty()).map(PathBuf::from))
+}
+
+/// Directory of the config, the history and the locks
+pub fn home_dir() -> PathBuf {
+    custom_home().unwrap_or_else(|| config_dir().unwrap().join("fmods"))
+}
+
+/// Directory of cached data, inside the custom home if there is one
+pub fn cache_home() -> PathBuf {
+    match custom_home() {
+        Some(home) => home.join("cache"),
+        None => cache_dir().unwrap().join("fmods")
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    home_dir().join("config.toml")
+}
 
 impl Config {
-    pub fn load() -> Self {
-        let path = config_dir().unwrap().join("fmods/config.toml");
+    /// Reads the config file, the default config if there is none yet.
+    ///
+    /// A broken file is an error rather than the default config, saving that would overwrite the user's settings.
+    pub fn load() -> Result<Self, Error> {
+        let path = config_path();
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(Error::Read(format!("{}: {}", path.display(), err)))
+        };
 
-        toml::from_str(&match std::fs::read_to_string(path) {
-            Ok(str) => str,
-            Err(_) => return Self::default()
-        }).unwrap_or(Self::default())
+        toml::from_str(&text).map_err(|err| Error::Parse(format!("{}: {}", path.display(), err)))
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn Error>> {
-        let path = config_dir().unwrap().join("fmods/config.toml");
+    /// The instances with their paths relative to `home` where possible.
+    pub fn export_instances(&self, home: Option<&Path>) -> InstanceRegistry {
+        let instances = self.instances.iter().map(|(name, path)| {
+            let path = match home {
+                Some(home) => relative_to_home(path, home),
+                None => path.clone()
+            };
 
-        OpenOptions::new()
+            (name.clone(), RegistryEntry { path, factorio_version: self.mods_only.get(name).cloned() })
+        }).collect();
+
+        InstanceRegistry { instances }
+    }
+
+    /// Adds the instance, replacing an existing one with the same name.
+    pub fn add_instance(&mut self, name: &str, path: PathBuf, factorio_version: Option<Version>) {
+        self.instances.insert(name.to_string(), path);
+        match factorio_version {
+            Some(factorio_version) => self.mods_only.insert(name.to_string(), factorio_version),
+            None => self.mods_only.remove(name)
+        };
+    }
+
+    /// Opens the instance with the given name, None if there is no such instance.
+    pub fn open_instance(&self, name: &str) -> Option<Result<Instance, instance::Error>> {
+        let path = self.instances.get(name)?.clone();
+
+        Some(match self.mods_only.get(name) {
+            Some(factorio_version) => Instance::new_mods_only(path, factorio_version.clone()),
+            None => Instance::new(path)
+        })
+    }
+
+    /// The parsed pins, an invalid one is an error naming its mod.
+    pub fn pins(&self) -> Result<HashMap<String, VersionRange>, Error> {
+        self.pins.iter()
+            .map(|(name, range)| match VersionRange::from_str(range) {
+                Ok(range) => Ok((name.clone(), range)),
+                Err(_) => Err(Error::InvalidValue { key: format!("pins.{}", name), expected: "a version range like >=1.0, <2.0" })
+            })
+            .collect()
+    }
+
+    /// Credentials for the official portal: the configured ones, otherwise those the game stores
+    /// in `player-data.json` next to the mods directory or in the game directory.
+    pub fn credentials(&self, instance: &Instance) -> Option<Credentials> {
+        if let (Some(username), Some(token)) = (&self.username, &self.token) {
+            return Some(Credentials { username: username.clone(), token: token.clone() });
+        }
+
+        #[derive(Deserialize)]
+        struct PlayerData {
+            #[serde(rename = "service-username")]
+            username: Option<String>,
+            #[serde(rename = "service-token")]
+            token: Option<String>
+        }
+
+        let paths = [instance.mods_path.parent().map(|x| x.join("player-data.json")), Some(instance.path.join("player-data.json"))];
+
+        for path in paths.into_iter().flatten() {
+            let player_data: PlayerData = match std::fs::read_to_string(path).ok().and_then(|x| serde_json::from_str(&x).ok()) {
+                Some(player_data) => player_data,
+                None => continue
+            };
+
+            if let (Some(username), Some(token)) = (player_data.username, player_data.token) {
+                return Some(Credentials { username, token });
+            }
+        }
+
+        None
+    }
+
+    /// Saves the config, replacing the file only after the new content is fully written.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = config_path();
+        let temp_path = path.with_extension("toml.tmp");
+
+        let mut file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(path)?
-            .write_all(toml::to_string(&self)?.as_bytes())?;
+            .open(&temp_path)?;
+
+        file.write_all(toml::to_string(&self)?.as_bytes())?;
+        file.sync_all()?;
+
+        rename(temp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Result<String, Error> {
+        Ok(match key {
+            "ask" => self.ask.to_string(),
+            "default_instance" => self.default_instance.clone().unwrap_or_default(),
+            "cache_ttl" => self.cache_ttl.to_string(),
+            "timeout" => self.timeout.to_string(),
+            "jobs" => self.jobs.to_string(),
+            "max_download" => self.max_download.clone().unwrap_or_default(),
+            "official_portal" => self.official_portal.to_string(),
+            "confirm.download" => self.confirm.download.to_string(),
+            "confirm.remove" => self.confirm.remove.to_string(),
+            "confirm.repair" => self.confirm.repair.to_string(),
+            _ => return Err(Error::UnknownKey(key.to_string()))
+        })
+    }
+
+    /// Sets the value of `key` parsed from a string. An empty `default_instance` or `max_download` unsets it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        const CONFIRM_MODE: &str = "always, never or a number of mods";
+
+        match key {
+            "ask" => self.ask = parse(key, value, "true or false")?,
+            "default_instance" => {
+                if value.is_empty() {
+                    self.default_instance = None;
+                } else if self.instances.contains_key(value) {
+                    self.default_instance = Some(value.to_string());
+                } else {
+                    return Err(Error::InvalidValue { key: key.to_string(), expected: "the name of an added instance" });
+                }
+            }
+            "cache_ttl" => self.cache_ttl = parse(key, value, "a number of seconds")?,
+            "timeout" => self.timeout = parse(key, value, "a number of seconds")?,
+            "jobs" => {
+                self.jobs = parse(key, value, "a positive number")?;
+                if self.jobs == 0 {
+                    return Err(Error::InvalidValue { key: key.to_string(), expected: "a positive number" });
+                }
+            }
+            "max_download" => {
+                if value.is_empty() {
+                    self.max_download = None;
+                } else if parse_size(value).is_ok() {
+                    self.max_download = Some(value.to_string());
+                } else {
+                    return Err(Error::InvalidValue { key: key.to_string(), expected: "a size like 500MiB or 2GB" });
+                }
+            }
+            "official_portal" => self.official_portal = parse(key, value, "true or false")?,
+            "confirm.download" => self.confirm.download = parse(key, value, CONFIRM_MODE)?,
+            "confirm.remove" => self.confirm.remove = parse(key, value, CONFIRM_MODE)?,
+            "confirm.repair" => self.confirm.repair = parse(key, value, CONFIRM_MODE)?,
+            _ => return Err(Error::UnknownKey(key.to_string()))
+        }
 
         Ok(())
     }
 }
 
+/// Instance definitions exported for another machine
+#[derive(Deserialize, Serialize, Default)]
+pub struct InstanceRegistry {
+    pub instances: BTreeMap<String, RegistryEntry>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RegistryEntry {
+    /// Paths in the home directory start with `~`
+    pub path: PathBuf,
+    /// Set for instances which are only a mods directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub factorio_version: Option<Version>
+}
+
+/// `path` relative to `home` as `~/...`, unchanged if it's outside of it.
+pub fn relative_to_home(path: &Path, home: &Path) -> PathBuf {
+    match path.strip_prefix(home) {
+        Ok(rest) => Path::new("~").join(rest),
+        Err(_) => path.to_path_buf()
+    }
+}
+
+/// Replaces the leading `~` of `path` with `base`.
+pub fn expand_home(path: &Path, base: &Path) -> PathBuf {
+    let mut components = path.components();
+
+    match components.next() {
+        Some(Component::Normal(first)) if first == "~" => base.join(components.as_path()),
+        _ => path.to_path_buf()
+    }
+}
+
+impl InstanceRegistry {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(std::fs::write(path, toml::to_string(self)?)?)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
             ask: true,
             default_instance: None,
+            cache_ttl: default_cache_ttl(),
+            timeout: default_timeout(),
+            post_install_command: None,
+            official_portal: false,
+            download_mirrors: default_download_mirrors(),
+            username: None,
+            token: None,
+            jobs: default_jobs(),
+            max_download: None,
             instances: HashMap::new(),
+            mods_only: HashMap::new(),
+            confirm: Confirm::default(),
+            renames: HashMap::new(),
+            pins: HashMap::new(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exported_paths_are_relative_to_home() {
+        let mut config = Config::default();
+        config.add_instance("local", PathBuf::from("/home/a/.factorio"), None);
+        config.add_instance("server", PathBuf::from("/srv/factorio/mods"), Some(Version::new(2, 0, 0)));
+
+        let registry = config.export_instances(Some(Path::new("/home/a")));
+
+        assert_eq!(registry.instances["local"].path, PathBuf::from("~/.factorio"));
+        assert_eq!(registry.instances["server"].path, PathBuf::from("/srv/factorio/mods"));
+        assert_eq!(registry.instances["server"].factorio_version, Some(Version::new(2, 0, 0)));
+
+        assert_eq!(expand_home(&registry.instances["local"].path, Path::new("/home/b")), PathBuf::from("/home/b/.factorio"));
+        assert_eq!(expand_home(&registry.instances["server"].path, Path::new("/home/b")), PathBuf::from("/srv/factorio/mods"));
+    }
+
+    #[test]
+    fn all_fields_are_known() {
+        let config = Config {
+            default_instance: Some(String::new()),
+            post_install_command: Some(String::new()),
+            username: Some(String::new()),
+            token: Some(String::new()),
+            max_download: Some(String::new()),
+            ..Config::default()
+        };
+
+        assert_eq!(unknown_keys(&toml::to_string(&config).unwrap()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn misspelled_keys_have_suggestions() {
+        let keys = unknown_keys("ask = true\njbos = 2\nfoo = 1\n[confirm]\ndownlaod = \"never\"\n").unwrap();
+
+        assert_eq!(keys, vec![
+            UnknownKey { key: "confirm.downlaod".to_string(), suggestion: Some("confirm.download".to_string()) },
+            UnknownKey { key: "foo".to_string(), suggestion: None },
+            UnknownKey { key: "jbos".to_string(), suggestion: Some("jobs".to_string()) },
+        ]);
+    }
+}
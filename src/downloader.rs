@@ -1,31 +1,477 @@
+use crate::http;
 use crate::instance::Instance;
-use crate::mod_info::Version;
-use std::error::Error;
-use std::io::Cursor;
-use std::path::PathBuf;
+use crate::mod_info::{Dependency, Version};
+use crate::trace::trace;
+use crate::utils::parallel_map;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use sha1_smol::Sha1;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{copy, BufRead, BufReader, Read, Write};
+use std::fs::{create_dir_all, remove_file, rename, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use url::Url;
 use zip::ZipArchive;
 
 pub struct Downloader {
-    path: PathBuf
+    path: PathBuf,
+    /// Expected sha1 of the archives of some mods, by mod id
+    checksums: HashMap<String, String>,
+    source: Source
+}
+
+/// Credentials of a mods.factorio.com account
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub token: String
+}
+
+/// Mirror used if none are configured
+pub const DEFAULT_MIRROR: &str = "https://mods-storage.re146.dev/{id}/{version}.zip";
+
+/// Where the mod archives are downloaded from
+#[derive(Clone)]
+pub enum Source {
+    /// URL templates with `{id}` and `{version}`, tried in order until one works
+    Mirrors(Vec<String>),
+    /// The official portal, downloads fail without credentials
+    Portal(Option<Credentials>)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ChecksumMismatch { expected: String, actual: String },
+    MissingInfoJson,
+    MissingCredentials,
+    ReleaseNotFound,
+    NoMirrors,
+    /// The response isn't a zip archive, e.g. an error page of the mirror
+    NotAnArchive { content_type: Option<String>, first_bytes: Vec<u8> }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ChecksumMismatch { expected, actual } =>
+                write!(f, "Checksum mismatch (expected {}, got {})", expected, actual),
+            Error::MissingInfoJson => f.write_str("The archive has no info.json"),
+            Error::MissingCredentials => f.write_str("Downloading from the official portal needs credentials, \
+log in to the game or set username and token in the config"),
+            Error::ReleaseNotFound => f.write_str("The release is not on the portal"),
+            Error::NoMirrors => f.write_str("No download mirrors are configured"),
+            Error::NotAnArchive { content_type, first_bytes } =>
+                write!(f, "Expected a zip archive, got {} (Content-Type: {}, starts with {:?}), the mirror may be down",
+                       describe_payload(first_bytes), content_type.as_deref().unwrap_or("unknown"),
+                       String::from_utf8_lossy(first_bytes))
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const EMPTY_ZIP_MAGIC: &[u8] = b"PK\x05\x06";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Number of leading bytes kept for `Error::NotAnArchive`
+const FIRST_BYTES: usize = 32;
+
+/// Rough description of what the bytes look like, for errors
+fn describe_payload(bytes: &[u8]) -> &'static str {
+    let text = String::from_utf8_lossy(bytes).trim_start().to_lowercase();
+
+    if bytes.is_empty() {
+        "an empty response"
+    } else if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        "HTML"
+    } else if text.starts_with('{') || text.starts_with('[') {
+        "JSON"
+    } else {
+        "unknown data"
+    }
+}
+
+/// Copies the payload into `out`, decompressing it if it's gzip-compressed, and checks that the result is a zip archive.
+///
+/// Responses declared as a web page or JSON are rejected even before looking at the content.
+fn unpack_payload(reader: impl Read, content_type: Option<String>, out: &mut impl Write) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let declared_text = content_type.as_ref()
+        .is_some_and(|x| x.starts_with("text/") || x.starts_with("application/json"));
+
+    let mut reader = BufReader::new(reader);
+    let mut reader: Box<dyn Read + '_> = if reader.fill_buf()?.starts_with(GZIP_MAGIC) && !declared_text {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut first_bytes = Vec::new();
+    reader.by_ref().take(FIRST_BYTES as u64).read_to_end(&mut first_bytes)?;
+
+    if declared_text || (!first_bytes.starts_with(ZIP_MAGIC) && !first_bytes.starts_with(EMPTY_ZIP_MAGIC)) {
+        return Err(Error::NotAnArchive { content_type, first_bytes }.into());
+    }
+
+    out.write_all(&first_bytes)?;
+    copy(&mut reader, out)?;
+
+    Ok(())
+}
+
+fn mirror_url(template: &str, id: &str, version: &Version) -> String {
+    template.replace("{id}", id).replace("{version}", &version.to_string())
+}
+
+/// Whether the next mirror should be tried after `err`: the mirror is unreachable, too slow, broken
+/// or answers with something else than the archive. Other errors would be the same for every mirror.
+fn is_mirror_failure(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    if let Some(err) = err.downcast_ref::<ureq::Error>() {
+        return match err {
+            ureq::Error::StatusCode(status) => *status >= 500,
+            ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed | ureq::Error::HostNotFound | ureq::Error::Io(_) => true,
+            _ => false
+        };
+    }
+
+    // the connection may break while the body is read
+    err.is::<std::io::Error>() || matches!(err.downcast_ref::<Error>(), Some(Error::NotAnArchive { .. }))
+}
+
+/// Reader reporting the number of bytes read so far and the expected total
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: u64,
+    total: Option<u64>,
+    progress: &'a dyn Fn(u64, Option<u64>)
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.read += count as u64;
+        (self.progress)(self.read, self.total);
+
+        Ok(count)
+    }
+}
+
+/// File which is removed when dropped, for archives which are only needed until they are extracted
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        _ = remove_file(&self.0);
+    }
+}
+
+/// Sha1 of a file, read in chunks
+fn file_sha1(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut sha1 = Sha1::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(sha1.digest().to_string()),
+            count => sha1.update(&buffer[..count])
+        }
+    }
+}
+
+fn verify_sha1(path: &Path, sha1: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let actual = file_sha1(path)?;
+    if !actual.eq_ignore_ascii_case(sha1) {
+        return Err(Error::ChecksumMismatch { expected: sha1.to_string(), actual }.into());
+    }
+
+    Ok(())
+}
+
+/// `info.json` of a mod archive
+#[derive(Deserialize)]
+pub struct ArchiveInfo {
+    pub name: String,
+    pub version: Version,
+    pub factorio_version: Version,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>
+}
+
+/// Reads `info.json` from the top folder of a mod archive.
+pub fn read_archive_info(path: &Path) -> Result<ArchiveInfo, Box<dyn std::error::Error + Send + Sync>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+
+    let name = match archive.file_names().find(|x| x.matches('/').count() == 1 && x.ends_with("/info.json")) {
+        Some(name) => name.to_string(),
+        None => return Err(Error::MissingInfoJson.into())
+    };
+
+    Ok(serde_json::from_reader(archive.by_name(&name)?)?)
 }
 
 impl Downloader {
     pub fn new(instance: &Instance) -> Self {
         Downloader {
             path: instance.mods_path.clone(),
+            checksums: HashMap::new(),
+            source: Source::Mirrors(vec![DEFAULT_MIRROR.to_string()]),
+        }
+    }
+
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Authenticated download link of a release on the official portal
+    fn portal_url(&self, id: &str, version: &Version, credentials: &Credentials) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        #[derive(Deserialize)]
+        struct PortalRelease {
+            version: Version,
+            download_url: String
+        }
+
+        #[derive(Deserialize)]
+        struct PortalMod {
+            releases: Vec<PortalRelease>
+        }
+
+        let portal_mod: PortalMod = http::agent().get(format!("https://mods.factorio.com/api/mods/{}", id))
+            .call()?
+            .body_mut()
+            .read_json()?;
+
+        let release = match portal_mod.releases.into_iter().find(|x| &x.version == version) {
+            Some(release) => release,
+            None => return Err(Error::ReleaseNotFound.into())
+        };
+
+        let url = Url::parse_with_params(&format!("https://mods.factorio.com{}", release.download_url),
+                                         [("username", &credentials.username), ("token", &credentials.token)])?;
+
+        Ok(url.to_string())
+    }
+
+    /// Verifies the archives of the given mods against their sha1 in `download_all`.
+    pub fn with_checksums(mut self, checksums: HashMap<String, String>) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// URLs of the archive in the order they are tried
+    fn archive_urls(&self, id: &str, version: &Version) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        match &self.source {
+            Source::Mirrors(templates) => Ok(templates.iter().map(|x| mirror_url(x, id, version)).collect()),
+            Source::Portal(Some(credentials)) => Ok(vec![self.portal_url(id, version, credentials)?]),
+            Source::Portal(None) => Err(Error::MissingCredentials.into())
         }
     }
 
-    pub fn download(&self, id: String, version: Version) -> Result<(), Box<dyn Error>> {
-        let mut response = ureq::get(format!("https://mods-storage.re146.dev/{}/{}.zip", id, version))
-            .call()?;
+    /// Streams the archive into the file at `path`, so a big mod is never held in memory.
+    /// The mirrors are tried in order, the error of the last one is returned if all of them fail.
+    ///
+    /// `progress` is called with the downloaded bytes and the size the server reports, if any.
+    fn fetch(&self, id: &str, version: &Version, path: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut last_error = None;
+
+        for url in self.archive_urls(id, version)? {
+            match self.fetch_url(&url, path, progress) {
+                Ok(()) => {
+                    trace!("{} {}: downloaded from {}", id, version, url);
+                    return Ok(());
+                }
+                Err(err) if is_mirror_failure(err.as_ref()) => {
+                    trace!("{} {}: {} failed: {}", id, version, url, err);
+                    last_error = Some(err);
+                }
+                Err(err) => return Err(err)
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| Error::NoMirrors.into()))
+    }
 
-        // disable read_to_vec size limit
-        let bytes = response.body_mut().with_config().read_to_vec()?;
+    fn fetch_url(&self, url: &str, path: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut response = http::agent().get(url).call()?;
+
+        let content_type = response.headers().get("content-type")
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_lowercase());
+        let total = response.headers().get("content-length")
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse().ok());
+
+        // disable the reader size limit, a Content-Encoding is already decoded by ureq
+        let reader = ProgressReader { inner: response.body_mut().with_config().reader(), read: 0, total, progress };
+        unpack_payload(reader, content_type, &mut File::create(path)?)
+    }
+
+    /// Downloads the archive into a temporary file in `dir`, which is removed when the result is dropped.
+    fn fetch_temp(&self, id: &str, version: &Version, dir: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<TempFile, Box<dyn std::error::Error + Send + Sync>> {
+        let temp = TempFile(dir.join(format!(".{}_{}.{}.part", id, version, process::id())));
+        self.fetch(id, version, &temp.0, progress)?;
+
+        Ok(temp)
+    }
+
+    /// Downloads the archive next to the mods rather than into the system temp dir, which may be in memory.
+    fn fetch_to_mods(&self, id: &str, version: &Version, progress: &dyn Fn(u64, Option<u64>)) -> Result<TempFile, Box<dyn std::error::Error + Send + Sync>> {
+        // instances don't create the mods directory when they are opened
+        create_dir_all(&self.path)?;
+        self.fetch_temp(id, version, &self.path, progress)
+    }
 
-        let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    fn extract(&self, path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        create_dir_all(&self.path)?;
         archive.extract(&self.path)?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn download(&self, id: String, version: Version) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let archive = self.fetch_to_mods(&id, &version, &|_, _| {})?;
+        self.extract(&archive.0)
+    }
+
+    /// Same as `download`, but refuses to extract an archive whose sha1 differs from `sha1`.
+    pub fn download_checked(&self, id: String, version: Version, sha1: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.download_verified(&id, &version, sha1, &|_, _| {})
+    }
+
+    fn download_verified(&self, id: &str, version: &Version, sha1: &str, progress: &dyn Fn(u64, Option<u64>)) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let archive = self.fetch_to_mods(id, version, progress)?;
+        verify_sha1(&archive.0, sha1)?;
+
+        self.extract(&archive.0)
+    }
+
+    /// Downloads the archive and verifies it against `sha1` if it's known, then calls `remove_old` and extracts it.
+    /// The installed copy is only removed once the new archive is on the disk and readable.
+    pub fn download_replacing(&self, id: &str, version: &Version, sha1: Option<&str>,
+                              remove_old: &mut dyn FnMut() -> std::io::Result<()>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.fetch_replacing(id, version, sha1, &|_, _| {}, remove_old)
+    }
+
+    /// Same as `download_replacing` with the sha1 given to `with_checksums`, reporting the progress like `download_with_progress`.
+    pub fn replace_with_progress(&self, id: &str, version: &Version, progress: &dyn Fn(u64, Option<u64>),
+                                 remove_old: &mut dyn FnMut() -> std::io::Result<()>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.fetch_replacing(id, version, self.checksums.get(id).map(|x| x.as_str()), progress, remove_old)
+    }
+
+    fn fetch_replacing(&self, id: &str, version: &Version, sha1: Option<&str>, progress: &dyn Fn(u64, Option<u64>),
+                       remove_old: &mut dyn FnMut() -> std::io::Result<()>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let archive = self.fetch_to_mods(id, version, progress)?;
+        if let Some(sha1) = sha1 {
+            verify_sha1(&archive.0, sha1)?;
+        }
+
+        let mut zip = ZipArchive::new(File::open(&archive.0)?)?;
+        remove_old()?;
+        zip.extract(&self.path)?;
+
+        Ok(())
+    }
+
+    /// Extracts a mod archive from the disk into the mods directory.
+    pub fn install_archive(&self, path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.extract(path)
+    }
+
+    /// Saves the mod archive as `<dir>/<id>_<version>.zip` instead of extracting it.
+    pub fn save_archive(&self, id: &str, version: &Version, dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+        // an interrupted download doesn't leave a broken archive behind
+        let temp = self.fetch_temp(id, version, dir, &|_, _| {})?;
+
+        let path = dir.join(format!("{}_{}.zip", id, version));
+        rename(&temp.0, &path)?;
+
+        Ok(path)
+    }
+
+    /// Size of the archive as the server reports it without downloading, None if it's unknown.
+    pub fn archive_size(&self, id: &str, version: &Version) -> Option<u64> {
+        self.archive_urls(id, version).ok()?.into_iter().find_map(|url| {
+            let response = http::agent().head(url).call().ok()?;
+
+            response.headers().get("content-length")?.to_str().ok()?.parse().ok()
+        })
+    }
+
+    /// Total size of the archives of `mods` and the number of mods whose size is unknown.
+    pub fn total_size(&self, mods: &[(String, Version)], jobs: usize) -> (u64, usize) {
+        let sizes = parallel_map(mods, jobs, |(id, version)| self.archive_size(id, version));
+
+        (sizes.iter().flatten().sum(), sizes.iter().filter(|x| x.is_none()).count())
+    }
+
+    /// Downloads and extracts the mod, verifying it if its checksum is known.
+    /// `progress` is called with the downloaded bytes and the size the server reports, if any.
+    pub fn download_with_progress(&self, id: &str, version: &Version, progress: &dyn Fn(u64, Option<u64>)) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.checksums.get(id) {
+            Some(sha1) => self.download_verified(id, version, sha1, progress),
+            None => self.extract(&self.fetch_to_mods(id, version, progress)?.0)
+        }
+    }
+
+    /// Downloads all `mods` using up to `jobs` parallel downloads, results are in the order of `mods`.
+    pub fn download_all(&self, mods: &[(String, Version)], jobs: usize) -> Vec<Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        parallel_map(mods, jobs, |(id, version)| self.download_with_progress(id, version, &|_, _| {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn gzip_payload_is_decompressed() {
+        let zip = b"PK\x05\x06rest of an empty archive".to_vec();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&zip).unwrap();
+
+        let mut out = Vec::new();
+        unpack_payload(encoder.finish().unwrap().as_slice(), Some("application/gzip".to_string()), &mut out).unwrap();
+        assert_eq!(out, zip);
+    }
+
+    #[test]
+    fn payload_is_copied_past_the_checked_bytes() {
+        let mut zip = b"PK\x03\x04".to_vec();
+        zip.extend((0..100_000u32).map(|x| x as u8));
+
+        let mut out = Vec::new();
+        unpack_payload(zip.as_slice(), Some("application/zip".to_string()), &mut out).unwrap();
+        assert_eq!(out, zip);
+    }
+
+    #[test]
+    fn next_mirror_is_tried_only_after_mirror_failures() {
+        assert_eq!(mirror_url(DEFAULT_MIRROR, "foo", &Version::new(1, 2, 3)), "https://mods-storage.re146.dev/foo/1.2.3.zip");
+
+        let failure = |err: Box<dyn std::error::Error + Send + Sync>| is_mirror_failure(err.as_ref());
+        assert!(failure(ureq::Error::StatusCode(502).into()));
+        assert!(failure(ureq::Error::ConnectionFailed.into()));
+        assert!(failure(Error::NotAnArchive { content_type: None, first_bytes: vec![] }.into()));
+        assert!(!failure(ureq::Error::StatusCode(404).into()));
+        assert!(!failure(Error::MissingCredentials.into()));
+    }
+
+    #[test]
+    fn error_page_is_not_an_archive() {
+        let page = b"<!DOCTYPE html><html>502 Bad Gateway</html>".to_vec();
+
+        let error = unpack_payload(page.as_slice(), None, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "Expected a zip archive, got HTML (Content-Type: unknown, \
+starts with \"<!DOCTYPE html><html>502 Bad Gat\"), the mirror may be down");
+
+        let error = unpack_payload(&b"PK\x03\x04"[..], Some("text/html".to_string()), &mut Vec::new()).unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::NotAnArchive { content_type: Some(_), .. })));
+    }
+}
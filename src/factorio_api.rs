@@ -1,64 +1,474 @@
+use crate::cache::{Cache, Cached};
+use crate::http;
 use crate::instance::Instance;
 use crate::mod_info::DependencyType::Require;
-use crate::mod_info::{ModInfo, ModRelease};
-use crate::utils::is_mod_game_content;
+use crate::mod_info::{validate_mod_id, Dependency, ModInfo, ModRelease, ModShort, ModSummary, PartialVersion, ShortRelease, Version, VersionRange};
+use crate::trace::trace;
+use std::cmp::Ordering::{Greater, Less};
+use crate::utils::{is_mod_game_content, parallel_map};
+use std::collections::{BTreeMap, HashMap};
 use std::mem::take;
+use std::sync::{Mutex, OnceLock};
+use std::thread::sleep;
+use std::time::Duration;
+use ureq::http::Response;
+use ureq::Body;
+use serde::Deserialize;
 use url::Url;
 
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(120);
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// URL of the mod on the portal API, `suffix` selects the endpoint like `/full`
+fn mod_url(name: &str, suffix: &str) -> Result<String, ureq::Error> {
+    match Url::parse(&format!("https://mods.factorio.com/api/mods/{}{}", name, suffix)) {
+        Ok(url) => Ok(url.to_string()),
+        Err(err) => Err(ureq::Error::Other(err.into()))
+    }
+}
+
+/// Performs a GET request, waiting and retrying while the API answers with 429 Too Many Requests.
+fn call_with_rate_limit(url: &str) -> Result<Response<Body>, ureq::Error> {
+    let mut retries = 0;
+    let mut waited = Duration::ZERO;
+
+    loop {
+        let response = http::agent().get(url)
+            .config()
+            .http_status_as_error(false)
+            .build()
+            .call()?;
+
+        let status = response.status().as_u16();
+
+        if status == 429 && retries < MAX_RATE_LIMIT_RETRIES {
+            // only the delay-seconds form of Retry-After is supported
+            let retry_after = response.headers().get("retry-after")
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.trim().parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+
+            if waited + retry_after <= MAX_RATE_LIMIT_WAIT {
+                retries += 1;
+                waited += retry_after;
+                sleep(retry_after);
+                continue;
+            }
+        }
+
+        if status >= 400 {
+            return Err(ureq::Error::StatusCode(status));
+        }
+
+        return Ok(response);
+    }
+}
+
+/// Source of mod metadata used by the dependency resolver.
+pub trait ModSource {
+    /// Returns the mod with only the releases compatible with the instance, sorted by version.
+    fn get_mod(&self, name: &str) -> Result<ModInfo, ureq::Error>;
+
+    /// Versions the mod is pinned to, `get_mod` only returns releases within them
+    fn pin(&self, _name: &str) -> Option<&VersionRange> {
+        None
+    }
+}
+
 pub struct FactorioApi<'a> {
-    instance: &'a Instance
+    instance: &'a Instance,
+    /// Factorio version to check compatibility against instead of the instance's one
+    target: Option<Version>,
+    cache: Option<&'a Cache>,
+    renames: Option<&'a HashMap<String, String>>,
+    pins: Option<&'a HashMap<String, VersionRange>>,
+    /// Accept releases for any Factorio version with the same major
+    major_compat: bool,
+    /// Newest Factorio version each fetched mod has releases for, before filtering
+    seen: Mutex<HashMap<String, Version>>,
+    /// Whole mod list of the portal, it's big so it's fetched once and shared by all searches
+    portal_mods: OnceLock<Vec<ModSummary>>
 }
 
 impl<'a> FactorioApi<'a> {
     pub fn new(instance: &'a Instance) -> Self {
         FactorioApi {
-            instance
+            instance,
+            target: None,
+            cache: None,
+            renames: None,
+            pins: None,
+            major_compat: false,
+            seen: Mutex::new(HashMap::new()),
+            portal_mods: OnceLock::new()
         }
     }
 
-    pub fn get_mod(&self, name: &String) -> Result<ModInfo, ureq::Error> {
+    /// Api which selects releases for another Factorio version, e.g. to check an upgrade.
+    pub fn with_target(instance: &'a Instance, target: Version) -> Self {
+        FactorioApi::new(instance).targeting(target)
+    }
 
-        let mut url = format!("https://mods.factorio.com/api/mods/{}/full", name);
-        url = match Url::parse(url.as_str()) {
-            Ok(url) => url,
-            Err(err) => return Err(ureq::Error::Other(err.into()))
-        }.to_string();
+    /// Selects releases for the `target` Factorio version instead of the instance's one.
+    pub fn targeting(mut self, target: Version) -> Self {
+        self.target = Some(Version::new(target.major, target.minor, 0));
+        self
+    }
 
-        let mut response = ureq::get(url)
-            .call()?;
+    /// Uses `cache` for mod info, including mods which weren't found.
+    pub fn cached(mut self, cache: Option<&'a Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
 
-        let mut result: ModInfo = response.body_mut().read_json()?;
+    /// Fetches mods listed in `renames` by their new ids.
+    pub fn with_renames(mut self, renames: &'a HashMap<String, String>) -> Self {
+        self.renames = Some(renames);
+        self
+    }
 
-        result.releases = take(&mut result.releases).into_iter()
-            .filter(|x| self.is_release_compatible(x))
-            .collect();
+    /// Only uses the releases of pinned mods within their pins.
+    pub fn with_pins(mut self, pins: &'a HashMap<String, VersionRange>) -> Self {
+        self.pins = Some(pins);
+        self
+    }
+
+    /// Accepts releases declared for another minor version of Factorio with the same major,
+    /// e.g. for mods which haven't updated their `factorio_version` yet after a minor release.
+    pub fn with_major_compat(mut self, major_compat: bool) -> Self {
+        self.major_compat = major_compat;
+        self
+    }
+
+    /// Fetches the mod with all of its releases, sorted by version and without duplicate versions.
+    pub fn get_mod_unfiltered(&self, name: &str) -> Result<ModInfo, ureq::Error> {
+        let name = self.renames.and_then(|x| x.get(name)).map_or(name, |x| x.as_str());
+
+        // an invalid id can't exist on the portal, don't ask it
+        if let Err(err) = validate_mod_id(name) {
+            return Err(ureq::Error::Other(err.into()));
+        }
+
+        let body = self.fetch_cached(name, &mod_url(name, "/full")?)?;
+
+        let mut result: ModInfo = serde_json::from_str(&body).map_err(ureq::Error::Json)?;
+
+        if result.name.is_empty() {
+            result.name = name.to_string();
+        }
+
+        result.sort_releases();
+
+        Ok(result)
+    }
+
+    /// Fetches the mod from the lighter endpoint, whose releases only have their Factorio version. It's enough
+    /// to find the newest release, `get_mod_unfiltered` is needed for dependencies. A cached full answer is reused.
+    pub fn get_mod_short(&self, name: &str) -> Result<ModShort, ureq::Error> {
+        let name = self.renames.and_then(|x| x.get(name)).map_or(name, |x| x.as_str());
+
+        if let Err(err) = validate_mod_id(name) {
+            return Err(ureq::Error::Other(err.into()));
+        }
+
+        let body = match self.cache.and_then(|cache| cache.get(name)) {
+            Some(Cached::Found(body)) => body,
+            Some(Cached::NotFound) => return Err(ureq::Error::StatusCode(404)),
+            // ids have no dots, the key can't be taken by a mod
+            None => self.fetch_cached(&format!("{}.short", name), &mod_url(name, "")?)?
+        };
+
+        let mut result: ModShort = serde_json::from_str(&body).map_err(ureq::Error::Json)?;
+
+        if result.name.is_empty() {
+            result.name = name.to_string();
+        }
 
         result.releases.sort_by(|x1, x2| x1.version.cmp(&x2.version));
 
         Ok(result)
     }
 
-    fn is_release_compatible(&self, mod_release: &ModRelease) -> bool {
-        if mod_release.info_json.factorio_version != self.instance.version {
-            return false;
+    /// Answer of the portal at `url`, stored in the cache under `key` including "not found".
+    fn fetch_cached(&self, key: &str, url: &str) -> Result<String, ureq::Error> {
+        if let Some(cached) = self.cache.and_then(|cache| cache.get(key)) {
+            return match cached {
+                Cached::Found(body) => Ok(body),
+                Cached::NotFound => Err(ureq::Error::StatusCode(404))
+            };
+        }
+
+        match call_with_rate_limit(url) {
+            Ok(mut response) => {
+                let body = response.body_mut().read_to_string()?;
+                if let Some(cache) = self.cache {
+                    cache.put(key, Some(&body));
+                }
+                Ok(body)
+            }
+            Err(ureq::Error::StatusCode(404)) => {
+                if let Some(cache) = self.cache {
+                    cache.put(key, None);
+                }
+                Err(ureq::Error::StatusCode(404))
+            }
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Fetches several mods like `get_mod`, using up to `jobs` parallel requests. Results are in the order of `names`.
+    pub fn get_mods(&self, names: &[String], jobs: usize) -> Vec<Result<ModInfo, ureq::Error>> {
+        parallel_map(names, jobs, |name| self.get_mod(name))
+    }
+
+    /// Searches mods whose id or title contains `query`, most downloaded first.
+    ///
+    /// The portal has no text search, so the whole mod list is fetched and filtered here.
+    /// The list of all mods is fetched only once, repeated searches filter the same copy.
+    /// With `compatible_only` the portal only returns mods with releases for the instance's Factorio version.
+    pub fn search(&self, query: &str, compatible_only: bool) -> Result<Vec<ModSummary>, ureq::Error> {
+        let compatible;
+        let mods = if compatible_only {
+            compatible = self.fetch_mod_list(Some(PartialVersion::major_minor(&self.instance.version)))?;
+            &compatible
+        } else {
+            match self.portal_mods.get() {
+                Some(mods) => mods,
+                None => {
+                    let mods = self.fetch_mod_list(None)?;
+                    self.portal_mods.get_or_init(|| mods)
+                }
+            }
+        };
+
+        let query = query.to_lowercase();
+        let mut result: Vec<ModSummary> = mods.iter()
+            .filter(|x| x.name.to_lowercase().contains(&query) || x.title.to_lowercase().contains(&query))
+            .cloned()
+            .collect();
+
+        result.sort_by(|x1, x2| x2.downloads_count.cmp(&x1.downloads_count));
+
+        Ok(result)
+    }
+
+    fn fetch_mod_list(&self, version: Option<PartialVersion>) -> Result<Vec<ModSummary>, ureq::Error> {
+        #[derive(Deserialize)]
+        struct ModList {
+            results: Vec<ModSummary>
         }
 
+        let mut url = String::from("https://mods.factorio.com/api/mods?page_size=max&hide_deprecated=true");
+        if let Some(version) = version {
+            url += &format!("&version={}", version);
+        }
+
+        let mut response = call_with_rate_limit(url.as_str())?;
+
+        // the full list is bigger than the default read_json limit
+        let bytes = response.body_mut().with_config().read_to_vec()?;
+        let list: ModList = serde_json::from_slice(&bytes).map_err(ureq::Error::Json)?;
+
+        Ok(list.results)
+    }
+
+    fn record_seen(&self, mod_info: &ModInfo) {
+        if let Some(newest) = mod_info.releases.iter().map(|x| &x.info_json.factorio_version).max() {
+            self.seen.lock().unwrap().insert(mod_info.name.clone(), newest.clone());
+        }
+    }
+
+    /// Factorio version most of the fetched mods target if most of them have no releases
+    /// for the instance's version or newer, a hint that the game was upgraded before the mods.
+    pub fn outdated_target(&self) -> Option<Version> {
+        let factorio_version = PartialVersion::major_minor(self.target.as_ref().unwrap_or(&self.instance.version));
+        let seen = self.seen.lock().unwrap();
+
+        let older: Vec<Version> = seen.values()
+            .filter(|x| factorio_version.cmp_version(x) == Greater)
+            .map(|x| Version::new(x.major, x.minor, 0))
+            .collect();
+
+        if older.len() * 2 <= seen.len() {
+            return None;
+        }
+
+        let mut counts: BTreeMap<Version, usize> = BTreeMap::new();
+        for version in older {
+            *counts.entry(version).or_default() += 1;
+        }
+
+        counts.into_iter().max_by_key(|x| x.1).map(|x| x.0)
+    }
+
+    pub fn is_release_compatible(&self, mod_release: &ModRelease) -> bool {
+        self.check_release(mod_release) == Compatibility::Compatible
+    }
+
+    /// Like `is_release_compatible`, but the requirements of game content are unknown and not checked.
+    pub fn is_short_release_compatible(&self, release: &ShortRelease) -> bool {
+        self.accepts_factorio_version(&release.info_json.factorio_version)
+    }
+
+    fn accepts_factorio_version(&self, version: &Version) -> bool {
+        let factorio_version = PartialVersion::major_minor(self.target.as_ref().unwrap_or(&self.instance.version));
+        let accepted = if self.major_compat {
+            PartialVersion { major: factorio_version.major, minor: None, patch: None }
+        } else {
+            factorio_version
+        };
+
+        accepted.matches(version)
+    }
+
+    fn check_release(&self, mod_release: &ModRelease) -> Compatibility {
+        let factorio_version = PartialVersion::major_minor(self.target.as_ref().unwrap_or(&self.instance.version));
+        if !self.accepts_factorio_version(&mod_release.info_json.factorio_version) {
+            return Compatibility::WrongFactorioVersion;
+        }
+
+        // game content is versioned together with the game, so a requirement like `base >= 2.0`
+        // is a constraint on the Factorio version even if factorio_version matches
         for dependency in mod_release.info_json.dependencies.iter() {
             if dependency.dependency_type != Require || !is_mod_game_content(dependency.mod_id.as_str()) {
                 continue
             }
 
-            if let Some(version) = self.instance.game_content_versions.get(&dependency.mod_id) {
+            // the installed game content is irrelevant for another Factorio version, only its major.minor is known
+            if self.target.is_some() {
                 if let Some(required_version) = &dependency.version {
-                    if required_version > version {
-                        return false;
+                    if factorio_version.cmp_version(required_version) == Less {
+                        return Compatibility::UnmetGameContent(dependency.clone());
                     }
                 }
-            } else {
-                return false;
+
+                continue
+            }
+
+            let version = match self.instance.game_content_versions.get(&dependency.mod_id) {
+                Some(version) => version,
+                None => return Compatibility::UnmetGameContent(dependency.clone())
+            };
+
+            if !dependency.accepts(version) {
+                return Compatibility::UnmetGameContent(dependency.clone());
             }
         }
 
-        true
+        Compatibility::Compatible
+    }
+}
+
+#[derive(Eq, PartialEq)]
+enum Compatibility {
+    Compatible,
+    WrongFactorioVersion,
+    UnmetGameContent(Dependency)
+}
+
+impl ModSource for FactorioApi<'_> {
+    fn get_mod(&self, name: &str) -> Result<ModInfo, ureq::Error> {
+        let mut result = self.get_mod_unfiltered(name)?;
+        self.record_seen(&result);
+
+        trace!("{}: {} releases", name, result.releases.len());
+
+        result.releases = take(&mut result.releases).into_iter()
+            .filter(|x| {
+                let compatibility = self.check_release(x);
+
+                trace!("  {} (Factorio {}): {}", x.version, PartialVersion::major_minor(&x.info_json.factorio_version),
+                       match &compatibility {
+                           Compatibility::Compatible => "kept".to_string(),
+                           Compatibility::WrongFactorioVersion => "rejected, wrong Factorio version".to_string(),
+                           Compatibility::UnmetGameContent(dependency) => format!("rejected, requires {}", dependency)
+                       });
+
+                compatibility == Compatibility::Compatible
+            })
+            .collect();
+
+        if let Some(pin) = self.pin(&result.name) {
+            result.retain_pinned(pin);
+            trace!("{}: {} releases within the pin {}", result.name, result.releases.len(), pin);
+        }
+
+        Ok(result)
+    }
+
+    fn pin(&self, name: &str) -> Option<&VersionRange> {
+        self.pins?.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mod_info::ModReleaseInfoJson;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn instance() -> Instance {
+        let mut instance = Instance::fake(PathBuf::new(), vec![]);
+        instance.version = Version::new(1, 1, 0);
+        instance.game_content_versions.insert("base".to_string(), Version::new(1, 1, 109));
+
+        instance
+    }
+
+    fn release(factorio_version: &str, dependencies: &[&str]) -> ModRelease {
+        ModRelease {
+            version: Version::new(1, 0, 0),
+            info_json: ModReleaseInfoJson {
+                dependencies: dependencies.iter().map(|x| Dependency::from_str(x).unwrap()).collect(),
+                factorio_version: Version::from_str(factorio_version).unwrap(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn base_requirement_limits_factorio_version() {
+        let instance = instance();
+        let factorio_api = FactorioApi::new(&instance);
+
+        assert!(factorio_api.is_release_compatible(&release("1.1", &["base >= 1.1.0"])));
+        assert!(!factorio_api.is_release_compatible(&release("1.1", &["base >= 2.0"])));
+        assert!(!factorio_api.is_release_compatible(&release("1.1", &["~ base >= 1.1.110"])));
+        assert!(factorio_api.is_release_compatible(&release("1.1", &["? base >= 2.0"])));
+        assert!(!factorio_api.is_release_compatible(&release("2.0", &["base >= 1.1.0"])));
+    }
+
+    #[test]
+    fn outdated_target_needs_most_mods_to_be_older() {
+        let instance = instance();
+        let factorio_api = FactorioApi::new(&instance);
+
+        let mod_info = |name: &str, factorio_versions: &[&str]| ModInfo {
+            name: name.to_string(),
+            releases: factorio_versions.iter().map(|x| release(x, &[])).collect(),
+            ..Default::default()
+        };
+
+        factorio_api.record_seen(&mod_info("a", &["0.17", "1.0"]));
+        factorio_api.record_seen(&mod_info("b", &["1.1"]));
+        assert_eq!(factorio_api.outdated_target(), None);
+
+        factorio_api.record_seen(&mod_info("c", &["1.0"]));
+        assert_eq!(factorio_api.outdated_target(), Some(Version::new(1, 0, 0)));
+    }
+
+    #[test]
+    fn major_compat_accepts_other_minor_versions() {
+        let instance = instance();
+        let factorio_api = FactorioApi::new(&instance).with_major_compat(true);
+
+        assert!(factorio_api.is_release_compatible(&release("1.0", &[])));
+        assert!(factorio_api.is_release_compatible(&release("1.2", &["base >= 1.1.0"])));
+        assert!(!factorio_api.is_release_compatible(&release("1.2", &["base >= 1.2.0"])));
+        assert!(!factorio_api.is_release_compatible(&release("2.0", &[])));
+        assert!(!FactorioApi::new(&instance).is_release_compatible(&release("1.0", &[])));
     }
 }
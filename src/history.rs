@@ -0,0 +1,247 @@
+use crate::config::home_dir;
+use crate::mod_info::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs::{read_to_string, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Install,
+    Update,
+    Remove
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Action::Install => "install",
+            Action::Update => "update",
+            Action::Remove => "remove"
+        })
+    }
+}
+
+/// A performed operation on a mod, stored as a line of `history.jsonl`
+#[derive(Deserialize, Serialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub instance: String,
+    pub action: Action,
+    #[serde(rename = "mod")]
+    pub mod_id: String,
+    pub old_version: Option<Version>,
+    pub new_version: Option<Version>,
+    /// Id shared by all entries written by one run of fmods
+    #[serde(default)]
+    pub operation: u64,
+    /// Operation reverted by this entry
+    #[serde(default)]
+    pub undoes: Option<u64>
+}
+
+fn current_operation() -> u64 {
+    static OPERATION: OnceLock<u64> = OnceLock::new();
+
+    *OPERATION.get_or_init(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_nanos() as u64).unwrap_or(0)
+    })
+}
+
+impl HistoryEntry {
+    pub fn new(instance: &str, action: Action, mod_id: &str, old_version: Option<Version>, new_version: Option<Version>) -> Self {
+        HistoryEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0),
+            instance: instance.to_string(),
+            action,
+            mod_id: mod_id.to_string(),
+            old_version,
+            new_version,
+            operation: current_operation(),
+            undoes: None
+        }
+    }
+
+    pub fn undoing(mut self, operation: u64) -> Self {
+        self.undoes = Some(operation);
+        self
+    }
+}
+
+pub fn history_path() -> PathBuf {
+    home_dir().join("history.jsonl")
+}
+
+pub fn record(entry: &HistoryEntry) -> io::Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(history_path())?
+        .write_all(line.as_bytes())
+}
+
+/// Reads all recorded entries, oldest first. Lines which can't be parsed are skipped.
+pub fn load() -> Vec<HistoryEntry> {
+    match read_to_string(history_path()) {
+        Ok(str) => str.lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect(),
+        Err(_) => vec![]
+    }
+}
+
+/// Entries of the most recent operation on `instance` which wasn't undone yet, newest first.
+/// Undo operations themselves are never returned.
+pub fn last_operation<'a>(entries: &'a [HistoryEntry], instance: &str) -> Vec<&'a HistoryEntry> {
+    let undone: HashSet<u64> = entries.iter().filter_map(|x| x.undoes).collect();
+
+    let operation = entries.iter().rev()
+        .find(|x| x.instance == instance && x.undoes.is_none() && !undone.contains(&x.operation))
+        .map(|x| x.operation);
+
+    match operation {
+        Some(operation) => entries.iter().rev()
+            .filter(|x| x.instance == instance && x.operation == operation && x.undoes.is_none())
+            .collect(),
+        None => vec![]
+    }
+}
+
+/// Start of the period to show, parsed from a duration like `7d`, `24h`, `30m`, `2w`
+/// or a UTC date like `2024-05-01`, `2024-05-01 12:30` or `2024-05-01T12:30:00`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Since {
+    /// Seconds before now
+    Ago(u64),
+    /// Unix timestamp
+    At(u64)
+}
+
+#[derive(Debug)]
+pub struct InvalidSince(String);
+
+impl Display for InvalidSince {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"{}\" is neither a duration like 7d, 24h, 30m, 2w nor a date like 2024-05-01 or 2024-05-01 12:30", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSince {}
+
+impl Since {
+    /// Unix timestamp of the start of the period
+    pub fn cutoff(&self) -> u64 {
+        match self {
+            Since::Ago(seconds) => SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs()).unwrap_or(0)
+                .saturating_sub(*seconds),
+            Since::At(timestamp) => *timestamp
+        }
+    }
+}
+
+impl FromStr for Since {
+    type Err = InvalidSince;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidSince(s.to_string());
+
+        let unit = match s.chars().last() {
+            Some('m') => Some(60),
+            Some('h') => Some(3600),
+            Some('d') => Some(86400),
+            Some('w') => Some(7 * 86400),
+            _ => None
+        };
+
+        if let Some(unit) = unit {
+            let count: u64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+            return Ok(Since::Ago(count * unit));
+        }
+
+        let (date, time) = match s.split_once(['T', ' ']) {
+            Some((date, time)) => (date, Some(time)),
+            None => (s, None)
+        };
+
+        let date: Vec<u64> = date.split('-').map(|x| x.parse()).collect::<Result<_, _>>().map_err(|_| invalid())?;
+        let [year, month, day] = date[..] else { return Err(invalid()) };
+
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+            return Err(invalid());
+        }
+
+        let seconds = match time {
+            Some(time) => {
+                let time: Vec<u64> = time.split(':').map(|x| x.parse()).collect::<Result<_, _>>().map_err(|_| invalid())?;
+                match time[..] {
+                    [hours, minutes] if hours < 24 && minutes < 60 => hours * 3600 + minutes * 60,
+                    [hours, minutes, seconds] if hours < 24 && minutes < 60 && seconds < 60 =>
+                        hours * 3600 + minutes * 60 + seconds,
+                    _ => return Err(invalid())
+                }
+            }
+            None => 0
+        };
+
+        Ok(Since::At(days_from_civil(year, month, day) * 86400 + seconds))
+    }
+}
+
+/// Days since the Unix epoch of a date, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let seconds = timestamp % 86400;
+
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since_parses_durations_and_dates() {
+        assert_eq!(Since::from_str("7d").unwrap(), Since::Ago(7 * 86400));
+        assert_eq!(Since::from_str("24h").unwrap(), Since::Ago(24 * 3600));
+        assert_eq!(Since::from_str("1970-01-02").unwrap(), Since::At(86400));
+        assert_eq!(Since::from_str("2024-03-01T12:30").unwrap(), Since::At(1709296200));
+        assert_eq!(format_timestamp(1709296200), "2024-03-01 12:30:00");
+        assert!(Since::from_str("yesterday").is_err());
+        assert!(Since::from_str("2024-13-01").is_err());
+        assert!(Since::from_str("d").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use std::io;
+use std::io::ErrorKind;
+use std::sync::OnceLock;
+use std::time::Duration;
+use ureq::Agent;
+
+static AGENT: OnceLock<Agent> = OnceLock::new();
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An unreachable mirror should not take the whole timeout before the next one is tried
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+fn build(timeout: Duration) -> Agent {
+    Agent::config_builder()
+        .timeout_global(Some(timeout))
+        .timeout_connect(Some(CONNECT_TIMEOUT.min(timeout)))
+        .build()
+        .into()
+}
+
+/// Sets the timeout of whole requests, including reading the body. Has no effect once a request was made.
+pub fn init(timeout: Duration) {
+    _ = AGENT.set(build(timeout));
+}
+
+/// Agent shared by all requests to the portal and the mirror
+pub fn agent() -> &'static Agent {
+    AGENT.get_or_init(|| build(DEFAULT_TIMEOUT))
+}
+
+fn io_hint(err: &io::Error) -> Option<&'static str> {
+    match err.kind() {
+        ErrorKind::ConnectionRefused => Some(CONNECT_HINT),
+        ErrorKind::NetworkUnreachable | ErrorKind::HostUnreachable | ErrorKind::NetworkDown =>
+            Some("the network is unreachable, you seem to be offline"),
+        ErrorKind::TimedOut => Some(TIMEOUT_HINT),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::UnexpectedEof =>
+            Some("the connection was interrupted, try again"),
+        _ => None
+    }
+}
+
+const CONNECT_HINT: &str = "couldn't connect to the server, check your internet connection, proxy and firewall";
+const TIMEOUT_HINT: &str = "the server didn't answer in time, try again later or raise --timeout";
+
+/// What the user can do about a failed request, None if the error isn't a network problem.
+/// The sources of `err` are checked too.
+pub fn hint(err: &(dyn std::error::Error + 'static)) -> Option<&'static str> {
+    let mut current = Some(err);
+
+    while let Some(err) = current {
+        if let Some(err) = err.downcast_ref::<ureq::Error>() {
+            let hint = match err {
+                ureq::Error::HostNotFound => Some("the server's address couldn't be resolved, check your internet connection and DNS"),
+                ureq::Error::ConnectionFailed => Some(CONNECT_HINT),
+                ureq::Error::Timeout(_) => Some(TIMEOUT_HINT),
+                ureq::Error::StatusCode(status) if *status >= 500 => Some("the mod portal or the mirror may be down, try again later"),
+                ureq::Error::Io(err) => io_hint(err),
+                // the TLS variants depend on the enabled features
+                _ if err.to_string().to_lowercase().contains("tls") || err.to_string().to_lowercase().contains("certificate") =>
+                    Some("the secure connection failed, check the system clock and whether a proxy intercepts HTTPS"),
+                _ => None
+            };
+
+            if hint.is_some() {
+                return hint;
+            }
+        }
+
+        if let Some(hint) = err.downcast_ref::<io::Error>().and_then(io_hint) {
+            return Some(hint);
+        }
+
+        current = err.source();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_failures_have_hints() {
+        assert_eq!(hint(&ureq::Error::ConnectionFailed), Some(CONNECT_HINT));
+        assert_eq!(hint(&ureq::Error::Io(io::Error::from(ErrorKind::TimedOut))), Some(TIMEOUT_HINT));
+        assert!(hint(&ureq::Error::StatusCode(503)).is_some());
+        assert_eq!(hint(&ureq::Error::StatusCode(404)), None);
+    }
+}
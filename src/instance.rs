@@ -1,24 +1,65 @@
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
-use std::fs::{create_dir, read_dir, remove_dir_all, File};
+use std::fs::{canonicalize, read_dir, read_to_string, remove_dir_all, remove_file};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
 use dirs::config_dir;
-use serde::Deserialize;
-use crate::mod_info::Version;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::mod_info::{Dependency, Version};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct InstalledMod {
     pub version: Version,
-    pub name: String
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<Dependency>
+}
+
+/// Factorio version and mods of an instance kept in a file, for planning without the game installed
+#[derive(Deserialize, Serialize)]
+pub struct Manifest {
+    pub factorio_version: Version,
+    /// Versions of the game content other than base, like `space-age`
+    #[serde(default)]
+    pub game_content: HashMap<String, Version>,
+    #[serde(default)]
+    pub mods: Vec<InstalledMod>
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(std::fs::write(path, toml::to_string(self)?)?)
+    }
+}
+
+/// A mod folder without a readable `info.json`
+pub struct BrokenMod {
+    pub name: String,
+    pub version: Version,
+    pub path: PathBuf
 }
 
 pub struct Instance {
     pub path: PathBuf,
     pub version: Version,
     pub game_content_versions: HashMap<String, Version>,
-    pub mods: Vec<InstalledMod>,
-    pub mods_path: PathBuf
+    /// Installed mods, read on the first call of `mods()`
+    pub mods: OnceLock<Vec<InstalledMod>>,
+    /// Mods directory with symlinks resolved
+    pub mods_path: PathBuf,
+    /// Mods directory as configured, before resolving symlinks
+    pub configured_mods_path: PathBuf,
+    /// The instance is only a mods directory, `path` is the mods directory itself
+    pub mods_only: bool,
+    /// Made from a manifest, there is no mods directory to change
+    pub read_only: bool
 }
 
 #[derive(Debug)]
@@ -38,6 +79,45 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
+/// Removes commas directly followed by `}` or `]`, ignoring the content of strings.
+fn strip_trailing_commas(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, char) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if char == '\\' {
+                escaped = true;
+            } else if char == '"' {
+                in_string = false;
+            }
+        } else if char == '"' {
+            in_string = true;
+        } else if char == ',' {
+            let next = text[index + 1..].trim_start().chars().next();
+            if matches!(next, Some('}') | Some(']')) {
+                continue
+            }
+        }
+
+        result.push(char);
+    }
+
+    result
+}
+
+/// Parses an `info.json`, tolerating a UTF-8 BOM and trailing commas which the game accepts too.
+fn parse_info_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let text = read_to_string(path).ok()?;
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+
+    serde_json::from_str(text).ok()
+        .or_else(|| serde_json::from_str(&strip_trailing_commas(text)).ok())
+}
+
 fn read_mods<P: AsRef<Path>>(path: P) -> io::Result<Vec<InstalledMod>> {
     let mut result = Vec::new();
 
@@ -48,16 +128,29 @@ fn read_mods<P: AsRef<Path>>(path: P) -> io::Result<Vec<InstalledMod>> {
             continue
         }
 
-        if let Ok(file) = File::open(path.join("info.json")) {
-            if let Ok(mod_info) = serde_json::from_reader(file) {
-                result.push(mod_info);
-            }
+        if let Some(mod_info) = parse_info_json(&path.join("info.json")) {
+            result.push(mod_info);
         }
     }
 
     Ok(result)
 }
 
+fn dir_size(path: &Path) -> u64 {
+    let entries = match read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0
+    };
+
+    entries.flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0
+        })
+        .sum()
+}
+
 impl Instance {
     pub fn new(path: PathBuf) -> Result<Self, Error> {
         if !path.is_dir() {
@@ -82,28 +175,335 @@ impl Instance {
             None => return Err(Error::BrokenInstance)
         };
 
-        let mods_path = config_dir().unwrap().join("Factorio/mods");
-        let mods = match read_mods(&mods_path) {
-            Ok(mods) => mods,
-            Err(_) => {
-                let _ = create_dir(&mods_path);
-                vec![]
-            }
-        };
+        let configured_mods_path = config_dir().unwrap().join("Factorio/mods");
+
+        Ok(Self::with_mods_path(path, version, game_content_versions, configured_mods_path, false))
+    }
+
+    /// Whether the Factorio version was detected, with an unknown version no release is compatible
+    pub fn has_factorio_version(&self) -> bool {
+        let unknown = Version::new(0, 0, 0);
+        self.version != unknown && self.game_content_versions.get("base").is_some_and(|x| *x != unknown)
+    }
 
-        Ok(Instance{
+    /// Instance which is only a mods directory, e.g. of a headless server, used with the given Factorio version.
+    pub fn new_mods_only(path: PathBuf, factorio_version: Version) -> Result<Self, Error> {
+        if !path.is_dir() {
+            return Err(Error::NotExist)
+        }
+
+        let version = Version::new(factorio_version.major, factorio_version.minor, 0);
+        // without the data directory the installed game content is unknown, base is assumed to match the game
+        let game_content_versions = HashMap::from([("base".to_string(), factorio_version)]);
+
+        Ok(Self::with_mods_path(path.clone(), version, game_content_versions, path, true))
+    }
+
+    fn with_mods_path(path: PathBuf, version: Version, game_content_versions: HashMap<String, Version>,
+                 configured_mods_path: PathBuf, mods_only: bool) -> Self {
+        // removing mods must not depend on whether the directory is a link or not
+        let mods_path = canonicalize(&configured_mods_path).unwrap_or(configured_mods_path.clone());
+
+        Instance{
             path,
             version,
             game_content_versions,
-            mods,
-            mods_path
-        })
+            mods: OnceLock::new(),
+            mods_path,
+            configured_mods_path,
+            mods_only,
+            read_only: false
+        }
+    }
+
+    /// Virtual instance with the mods of the manifest at `path`. It's only for resolving dependencies
+    /// and computing changes, removing mods does nothing.
+    pub fn from_manifest(path: PathBuf, manifest: Manifest) -> Self {
+        let mut game_content_versions = manifest.game_content;
+        game_content_versions.insert("base".to_string(), manifest.factorio_version.clone());
+
+        Instance {
+            version: Version::new(manifest.factorio_version.major, manifest.factorio_version.minor, 0),
+            game_content_versions,
+            mods: OnceLock::from(manifest.mods),
+            mods_path: path.clone(),
+            configured_mods_path: path.clone(),
+            path,
+            mods_only: true,
+            read_only: true
+        }
+    }
+
+    /// The Factorio version and installed mods, for planning changes elsewhere with `from_manifest`.
+    pub fn manifest(&self) -> Manifest {
+        let factorio_version = self.game_content_versions.get("base").unwrap_or(&self.version).clone();
+        let game_content = self.game_content_versions.iter()
+            .filter(|(name, _)| *name != "base")
+            .map(|(name, version)| (name.clone(), version.clone()))
+            .collect();
+
+        let mut mods = self.mods().clone();
+        mods.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Manifest { factorio_version, game_content, mods }
+    }
+
+    /// Installed mods. The mods directory is only read on the first call, a missing one has no mods.
+    pub fn mods(&self) -> &Vec<InstalledMod> {
+        self.mods.get_or_init(|| read_mods(&self.mods_path).unwrap_or_default())
+    }
+
+    /// Reads the info.json of an installed release from its `<id>_<version>` or `<id>` folder.
+    pub fn read_installed_mod(&self, id: &str, version: &Version) -> Option<InstalledMod> {
+        [format!("{}_{}", id, version), id.to_string()].iter()
+            .filter_map(|folder| parse_info_json::<InstalledMod>(&self.mods_path.join(folder).join("info.json")))
+            .find(|x| x.name == id && x.version == *version)
+    }
+
+    /// Opens the same instance again, e.g. to see the changes made to the mods directory.
+    pub fn reload(&self) -> Result<Instance, Error> {
+        if self.read_only {
+            Ok(Instance::from_manifest(self.path.clone(), self.manifest()))
+        } else if self.mods_only {
+            let factorio_version = self.game_content_versions.get("base").unwrap_or(&self.version);
+            Instance::new_mods_only(self.path.clone(), factorio_version.clone())
+        } else {
+            Instance::new(self.path.clone())
+        }
+    }
+
+    /// Finds `<name>_<version>` mod folders whose `info.json` is missing or can't be parsed,
+    /// e.g. after an interrupted extraction.
+    pub fn find_broken_mods(&self) -> Vec<BrokenMod> {
+        let mut result = Vec::new();
+
+        let entries = match read_dir(&self.mods_path) {
+            Ok(entries) => entries,
+            Err(_) => return result
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if !path.is_dir() {
+                continue
+            }
+
+            let readable = parse_info_json::<InstalledMod>(&path.join("info.json")).is_some();
+
+            if readable {
+                continue
+            }
+
+            let folder = entry.file_name().to_string_lossy().to_string();
+            if let Some((name, version)) = folder.rsplit_once('_') {
+                if let Ok(version) = Version::from_str(version) {
+                    result.push(BrokenMod { name: name.to_string(), version, path });
+                }
+            }
+        }
+
+        result
+    }
+
+    /// The same game installation as if no mods were installed
+    pub fn without_mods(&self) -> Instance {
+        Instance {
+            path: self.path.clone(),
+            version: self.version.clone(),
+            game_content_versions: self.game_content_versions.clone(),
+            mods: OnceLock::from(vec![]),
+            mods_path: self.mods_path.clone(),
+            configured_mods_path: self.configured_mods_path.clone(),
+            mods_only: self.mods_only,
+            read_only: self.read_only
+        }
+    }
+
+    /// Size of an installed release in bytes, of its `<id>_<version>` or `<id>` folder or its zip file
+    pub fn mod_size(&self, id: &str, version: &Version) -> u64 {
+        let folder = format!("{}_{}", id, version);
+
+        [self.mods_path.join(&folder), self.mods_path.join(id), self.mods_path.join(format!("{}.zip", folder))].iter()
+            .find(|x| x.exists())
+            .map(|path| if path.is_dir() { dir_size(path) } else { path.metadata().map(|x| x.len()).unwrap_or(0) })
+            .unwrap_or(0)
+    }
+
+    /// Total size of the files in the mods directory, in bytes
+    pub fn disk_usage(&self) -> u64 {
+        dir_size(&self.mods_path)
+    }
+
+    /// Removes every version of `mod_name` in the mods directory except `keep`, both extracted folders
+    /// and zip files. Versions are read from `info.json` and from the file names otherwise.
+    ///
+    /// Returns the removed paths.
+    pub fn prune_mod(&self, mod_name: &str, keep: &Version) -> Vec<PathBuf> {
+        let mut result = Vec::new();
+
+        if self.read_only {
+            return result;
+        }
+
+        let entries = match read_dir(&self.mods_path) {
+            Ok(entries) => entries,
+            Err(_) => return result
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            let version = match parse_info_json::<InstalledMod>(&path.join("info.json")) {
+                Some(info) if path.is_dir() => (info.name == mod_name).then_some(info.version),
+                _ => {
+                    let file_name = entry.file_name().to_string_lossy().to_string();
+                    let stem = file_name.strip_suffix(".zip").unwrap_or(&file_name);
+
+                    match stem.rsplit_once('_') {
+                        Some((name, version)) if name == mod_name => Version::from_str(version).ok(),
+                        _ => None
+                    }
+                }
+            };
+
+            if version.is_none_or(|x| &x == keep) {
+                continue
+            }
+
+            let removed = if path.is_dir() { remove_dir_all(&path) } else { remove_file(&path) };
+            if removed.is_ok() {
+                result.push(path);
+            }
+        }
+
+        result
     }
 
     pub fn remove_mod(&self, mod_name: &str) {
-        if let Some(info) = self.mods.iter().find(|x| x.name == mod_name) {
+        if self.read_only {
+            return;
+        }
+
+        if let Some(info) = self.mods().iter().find(|x| x.name == mod_name) {
             _ = remove_dir_all(self.mods_path.join(format!("{}_{}", &info.name, &info.version)));
             _ = remove_dir_all(self.mods_path.join(&info.name));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl Instance {
+    /// Mods only instance of Factorio 2.0 with the given installed mods, nothing is read from `mods_path` until it's reloaded
+    pub(crate) fn fake(mods_path: PathBuf, mods: Vec<InstalledMod>) -> Self {
+        Instance {
+            path: mods_path.clone(),
+            version: Version::new(2, 0, 0),
+            game_content_versions: HashMap::from([("base".to_string(), Version::new(2, 0, 28))]),
+            mods: OnceLock::from(mods),
+            mods_path: mods_path.clone(),
+            configured_mods_path: mods_path,
+            mods_only: true,
+            read_only: false
+        }
+    }
+}
+
+/// Fresh temporary directory with a folder containing `info.json` for each `(folder, info.json)` pair
+#[cfg(test)]
+pub(crate) fn fixture_dir(name: &str, folders: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fmods-{}-{}", name, std::process::id()));
+    _ = remove_dir_all(&dir);
+
+    for (folder, info_json) in folders {
+        std::fs::create_dir_all(dir.join(folder)).unwrap();
+        std::fs::write(dir.join(folder).join("info.json"), info_json).unwrap();
+    }
+
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+
+    #[test]
+    fn info_json_with_bom_and_trailing_commas() {
+        let dir = fixture_dir("read-mods", &[
+            ("bom_1.0.0", "\u{feff}{\"name\": \"bom\", \"version\": \"1.0.0\"}"),
+            ("commas_1.0.0", "{\"name\": \"commas\", \"version\": \"1.0.0\", \"dependencies\": [\"base\",],}"),
+            ("strings_1.0.0", "{\"name\": \"strings\", \"version\": \"1.0.0\", \"title\": \",}\"}"),
+            ("broken_1.0.0", "{\"name\": \"broken\""),
+        ]);
+
+        let mut names: Vec<String> = read_mods(&dir).unwrap().into_iter().map(|x| x.name).collect();
+        names.sort();
+
+        _ = remove_dir_all(&dir);
+
+        assert_eq!(names, vec!["bom", "commas", "strings"]);
+    }
+
+    #[test]
+    fn prune_keeps_only_the_installed_version() {
+        let dir = fixture_dir("prune", &[
+            ("foo", "{\"name\": \"foo\", \"version\": \"1.0.0\"}"),
+            ("foo_1.1.0", "{\"name\": \"foo\", \"version\": \"1.1.0\"}"),
+            ("foo_2.0.0", "{\"name\": \"foo\", \"version\": \"2.0.0\"}"),
+            ("foo_bar_1.0.0", "{\"name\": \"foo_bar\", \"version\": \"1.0.0\"}"),
+        ]);
+        write(dir.join("foo_0.9.0.zip"), "").unwrap();
+
+        let instance = Instance::fake(dir.clone(), vec![]);
+
+        let mut pruned: Vec<String> = instance.prune_mod("foo", &Version::new(2, 0, 0)).into_iter()
+            .map(|x| x.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        pruned.sort();
+
+        let remaining = dir.join("foo_2.0.0").exists() && dir.join("foo_bar_1.0.0").exists();
+        _ = remove_dir_all(&dir);
+
+        assert_eq!(pruned, vec!["foo", "foo_0.9.0.zip", "foo_1.1.0"]);
+        assert!(remaining);
+    }
+
+    #[test]
+    fn manifest_instance_has_the_declared_mods() {
+        let text = r#"
+            factorio_version = "2.0.28"
+            game_content = { space-age = "2.0.28" }
+
+            [[mods]]
+            name = "foo"
+            version = "1.2.0"
+            dependencies = ["base >= 2.0.0", "? bar"]
+        "#;
+
+        let instance = Instance::from_manifest(PathBuf::from("pack.toml"), toml::from_str(text).unwrap());
+
+        assert_eq!(instance.version, Version::new(2, 0, 0));
+        assert_eq!(instance.game_content_versions["space-age"], Version::new(2, 0, 28));
+        assert_eq!(instance.mods()[0].dependencies.len(), 2);
+
+        let saved = toml::to_string(&instance.manifest()).unwrap();
+        let reloaded = Instance::from_manifest(PathBuf::from("pack.toml"), toml::from_str(&saved).unwrap());
+        assert_eq!(reloaded.game_content_versions, instance.game_content_versions);
+        assert_eq!(reloaded.mods()[0].dependencies, instance.mods()[0].dependencies);
+    }
+
+    #[test]
+    fn zero_factorio_version_is_unknown() {
+        let detected = Instance::from_manifest(PathBuf::from("pack.toml"), toml::from_str("factorio_version = \"2.0.28\"").unwrap());
+        let unknown = Instance::from_manifest(PathBuf::from("pack.toml"), toml::from_str("factorio_version = \"0.0.0\"").unwrap());
+
+        assert!(detected.has_factorio_version());
+        assert!(!unknown.has_factorio_version());
+    }
+
+    #[test]
+    fn trailing_commas_inside_strings_are_kept() {
+        assert_eq!(strip_trailing_commas(r#"{"a": [1, 2,], "b": ",]",}"#), r#"{"a": [1, 2], "b": ",]"}"#);
+    }
+}
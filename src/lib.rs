@@ -0,0 +1,24 @@
+//! Factorio mod manager: instances, the mod portal client, the dependency resolver and the downloader.
+//!
+//! The `fmods` binary is a command line interface on top of this library.
+
+pub mod downloader;
+pub mod mod_info;
+pub mod factorio_api;
+pub mod instance;
+pub mod utils;
+pub mod config;
+pub mod mod_list;
+pub mod history;
+pub mod cache;
+pub mod http;
+pub mod trace;
+pub mod pipeline;
+pub mod lock;
+
+pub use config::Config;
+pub use downloader::Downloader;
+pub use factorio_api::{FactorioApi, ModSource};
+pub use instance::Instance;
+pub use mod_info::{Dependency, Version};
+pub use utils::{process_dependencies_of, Changes};
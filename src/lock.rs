@@ -0,0 +1,42 @@
+use crate::config::home_dir;
+use crate::instance::Instance;
+use sha1_smol::Sha1;
+use std::fs::{create_dir_all, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::PathBuf;
+
+/// Exclusive lock of a mods directory for the duration of a mutating operation, released when dropped.
+///
+/// The lock is advisory, it only keeps other fmods processes from changing the same mods at once.
+pub struct InstanceLock {
+    _file: File
+}
+
+/// Lock file of the instance's mods directory, so instances sharing a mods directory share the lock
+pub fn lock_path(instance: &Instance) -> PathBuf {
+    let key = Sha1::from(instance.mods_path.to_string_lossy().as_bytes()).digest().to_string();
+
+    home_dir().join("locks").join(format!("{}.lock", key))
+}
+
+impl InstanceLock {
+    /// Locks the instance, None if another process holds the lock.
+    pub fn try_acquire(instance: &Instance) -> io::Result<Option<Self>> {
+        let path = lock_path(instance);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(Some(InstanceLock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => Err(err)
+        }
+    }
+}
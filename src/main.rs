@@ -1,17 +1,36 @@
-use std::fs::create_dir;
-use dirs::config_dir;
-use crate::cli::cli;
+use std::env::var_os;
+use std::fs::create_dir_all;
+use std::io::{stdout, IsTerminal};
+use std::process::exit;
+use clap::Parser;
+use fmods::config;
+use fmods::trace;
+use crate::cli::{cli, Args, ColorMode};
 
-mod downloader;
-mod mod_info;
-mod factorio_api;
-mod instance;
-mod utils;
 mod cli;
-mod config;
+
+fn setup_colors(mode: ColorMode) {
+    let enabled = match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => !var_os("NO_COLOR").is_some_and(|x| !x.is_empty()) && stdout().is_terminal(),
+    };
+
+    colored::control::set_override(enabled);
+}
 
 fn main() {
-    _ = create_dir(config_dir().unwrap().join("fmods"));
+    let args = Args::parse();
+    if let Some(home) = &args.home {
+        config::set_home(home.clone());
+    }
+    _ = create_dir_all(config::home_dir());
+
+    setup_colors(args.color);
+    trace::set_verbose(args.verbose);
 
-    cli();
+    if let Err(err) = cli(args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
 }
@@ -1,5 +1,5 @@
-use serde::de::Visitor;
-use serde::{Deserialize, Deserializer};
+use serde::de::{DeserializeOwned, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::cmp::Ordering;
 use std::cmp::Ordering::{Equal, Greater, Less};
 use std::fmt::Formatter;
@@ -8,7 +8,8 @@ use std::num::ParseIntError;
 use std::str::FromStr;
 use crate::mod_info::DependencyType::{Conflict, Optional, Require};
 
-#[derive(Eq, PartialEq, Clone, Debug)]
+/// `Ord` compares all three parts, so versions equal by `cmp` have the same hash.
+#[derive(Eq, PartialEq, Hash, Clone, Debug, Default)]
 pub struct Version {
     pub major: i64,
     pub minor: i64,
@@ -83,6 +84,70 @@ impl Ord for Version {
     }
 }
 
+/// Version with omitted trailing components, e.g. `1.1` stands for any `1.1.x`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct PartialVersion {
+    pub major: i64,
+    pub minor: Option<i64>,
+    pub patch: Option<i64>
+}
+
+impl PartialVersion {
+    /// Major and minor of `version`, the form in which Factorio versions are compared.
+    pub fn major_minor(version: &Version) -> Self {
+        PartialVersion {
+            major: version.major,
+            minor: Some(version.minor),
+            patch: None
+        }
+    }
+
+    /// Compares only the components specified in `self`.
+    pub fn cmp_version(&self, version: &Version) -> Ordering {
+        self.major.cmp(&version.major)
+            .then_with(|| self.minor.map_or(Equal, |minor| minor.cmp(&version.minor)))
+            .then_with(|| self.patch.map_or(Equal, |patch| patch.cmp(&version.patch)))
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.cmp_version(version) == Equal
+    }
+}
+
+impl FromStr for PartialVersion {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('.');
+
+        let major = parts.next().unwrap_or_default().parse()?;
+        let minor = parts.next().map(|x| x.parse()).transpose()?;
+        let patch = parts.next().map(|x| x.parse()).transpose()?;
+
+        Ok(PartialVersion {
+            major,
+            minor,
+            patch
+        })
+    }
+}
+
+impl Display for PartialVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.major)?;
+
+        if let Some(minor) = self.minor {
+            write!(f, ".{}", minor)?;
+        }
+
+        if let Some(patch) = self.patch {
+            write!(f, ".{}", patch)?;
+        }
+
+        Ok(())
+    }
+}
+
 struct VersionVisitor;
 
 impl<'de> Visitor<'de> for VersionVisitor {
@@ -103,6 +168,15 @@ impl<'de> Visitor<'de> for VersionVisitor {
     }
 }
 
+impl Serialize for Version {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
 impl<'de> Deserialize<'de> for Version {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -112,19 +186,261 @@ impl<'de> Deserialize<'de> for Version {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug, Default)]
 pub struct ModInfo {
-    pub releases: Vec<ModRelease>
+    /// Current id of the mod, differs from the requested one if the mod was renamed
+    #[serde(default)]
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_releases")]
+    pub releases: Vec<ModRelease>,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub downloads_count: u64,
+    /// `changelog.txt` of the latest release, only in the full info
+    #[serde(default)]
+    pub changelog: Option<String>
+}
+
+/// Mod info of the short endpoint, the releases have no dependencies
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ModShort {
+    #[serde(default)]
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_releases")]
+    pub releases: Vec<ShortRelease>,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub downloads_count: u64
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ShortRelease {
+    pub version: Version,
+    pub info_json: ShortReleaseInfoJson,
+    /// ISO 8601 timestamp, e.g. `2024-10-21T12:30:00.000000Z`
+    #[serde(default)]
+    pub released_at: String
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ShortReleaseInfoJson {
+    pub factorio_version: Version
+}
+
+/// Malformed releases, e.g. with an unparseable version, are skipped instead of failing the whole mod.
+fn deserialize_releases<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned
+{
+    let values: Vec<serde_json::Value> = Deserialize::deserialize(deserializer)?;
+
+    Ok(values.into_iter()
+        .filter_map(|value| serde_json::from_value(value).ok())
+        .collect())
+}
+
+/// Tags are plain names in the current API, older responses use objects with a `name` field.
+fn deserialize_tags<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>
+{
+    let values: Vec<serde_json::Value> = Deserialize::deserialize(deserializer)?;
+
+    Ok(values.into_iter()
+        .filter_map(|value| match value {
+            serde_json::Value::String(name) => Some(name),
+            serde_json::Value::Object(mut object) => match object.remove("name") {
+                Some(serde_json::Value::String(name)) => Some(name),
+                _ => None
+            },
+            _ => None
+        })
+        .collect())
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug, Default)]
 pub struct ModRelease {
     pub version: Version,
-    pub info_json: ModReleaseInfoJson
+    pub info_json: ModReleaseInfoJson,
+    #[serde(default)]
+    pub sha1: Option<String>,
+    /// Name of the release archive, e.g. `foo_1.0.0.zip`
+    #[serde(default)]
+    pub file_name: String,
+    /// ISO 8601 timestamp, e.g. `2024-10-21T12:30:00.000000Z`
+    #[serde(default)]
+    pub released_at: String
+}
+
+/// Identifies a release by its archive instead of its version
+#[derive(Clone, Debug)]
+pub enum ReleaseSelector {
+    Sha1(String),
+    FileName(String)
+}
+
+impl ModInfo {
+    /// Keeps only the releases allowed by the pin of the mod.
+    pub fn retain_pinned(&mut self, pin: &VersionRange) {
+        self.releases.retain(|x| pin.accepts(&x.version));
+    }
+
+    /// Sorts the releases by version, keeping only the last listed release of each version.
+    pub fn sort_releases(&mut self) {
+        self.releases.sort_by(|x1, x2| x1.version.cmp(&x2.version));
+
+        // the sort is stable, reversed the last listed duplicate comes first and is kept
+        self.releases.reverse();
+        self.releases.dedup_by(|x1, x2| x1.version == x2.version);
+        self.releases.reverse();
+    }
+
+    pub fn find_release(&self, selector: &ReleaseSelector) -> Option<&ModRelease> {
+        self.releases.iter().find(|x| match selector {
+            ReleaseSelector::Sha1(sha1) => x.sha1.as_ref().is_some_and(|x| x.eq_ignore_ascii_case(sha1)),
+            ReleaseSelector::FileName(file_name) => &x.file_name == file_name
+        })
+    }
+}
+
+const MAX_MOD_ID_LENGTH: usize = 100;
+
+/// Reason why a string can't be a mod id
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidModId {
+    Empty,
+    TooLong,
+    /// Leading or trailing whitespace
+    Whitespace,
+    Character(char)
+}
+
+impl Display for InvalidModId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidModId::Empty => f.write_str("the mod id is empty"),
+            InvalidModId::TooLong => write!(f, "mod ids are at most {} characters long", MAX_MOD_ID_LENGTH),
+            InvalidModId::Whitespace => f.write_str("the mod id starts or ends with a space"),
+            InvalidModId::Character(char) =>
+                write!(f, "mod ids can't contain {:?}, only letters, digits, \"-\", \"_\" and spaces", char)
+        }
+    }
+}
+
+impl std::error::Error for InvalidModId {}
+
+/// Minimum Factorio version a mod requires, taken from its requirement of `base` which is versioned with the game.
+pub fn minimum_factorio_version(dependencies: &[Dependency]) -> Option<&Version> {
+    dependencies.iter()
+        .filter(|x| x.dependency_type == DependencyType::Require && x.mod_id == "base")
+        .filter(|x| matches!(x.operator, VersionOperator::GreaterOrEqual | VersionOperator::Greater | VersionOperator::Equal))
+        .find_map(|x| x.version.as_ref())
+}
+
+/// Changes of one release in a `changelog.txt`
+#[derive(Debug, Eq, PartialEq)]
+pub struct ChangelogEntry {
+    pub version: Version,
+    pub date: Option<String>,
+    /// Categories like `Bugfixes` with their items, in the order they are listed
+    pub sections: Vec<(String, Vec<String>)>
+}
+
+/// Parses the Factorio changelog format: entries separated by lines of dashes, each with a `Version:`,
+/// an optional `Date:` and indented categories of `- ` items. Entries with an invalid version are skipped.
+pub fn parse_changelog(text: &str) -> Vec<ChangelogEntry> {
+    let mut result = Vec::new();
+    let mut current: Option<ChangelogEntry> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with("---") && trimmed.chars().all(|x| x == '-') {
+            result.extend(current.take());
+        } else if let Some(version) = trimmed.strip_prefix("Version:") {
+            result.extend(current.take());
+            current = Version::from_str(version.trim()).ok()
+                .map(|version| ChangelogEntry { version, date: None, sections: Vec::new() });
+        } else if let Some(entry) = &mut current {
+            if let Some(date) = trimmed.strip_prefix("Date:") {
+                entry.date = Some(date.trim().to_string());
+            } else if let Some(item) = trimmed.strip_prefix("- ") {
+                if entry.sections.is_empty() {
+                    entry.sections.push((String::new(), Vec::new()));
+                }
+                entry.sections.last_mut().unwrap().1.push(item.to_string());
+            } else if let Some(category) = trimmed.strip_suffix(':').filter(|_| !line.starts_with("    ")) {
+                entry.sections.push((category.to_string(), Vec::new()));
+            } else if !trimmed.is_empty() {
+                // an item continued on the next line
+                if let Some(item) = entry.sections.last_mut().and_then(|x| x.1.last_mut()) {
+                    item.push(' ');
+                    item.push_str(trimmed);
+                }
+            }
+        }
+    }
+
+    result.extend(current);
+    result
+}
+
+/// Checks the charset of a mod id. Spaces are allowed since some old mods have them.
+pub fn validate_mod_id(id: &str) -> Result<(), InvalidModId> {
+    if id.is_empty() {
+        return Err(InvalidModId::Empty);
+    }
+
+    if id.chars().count() > MAX_MOD_ID_LENGTH {
+        return Err(InvalidModId::TooLong);
+    }
+
+    if id.trim() != id {
+        return Err(InvalidModId::Whitespace);
+    }
+
+    match id.chars().find(|x| !x.is_ascii_alphanumeric() && !matches!(x, '-' | '_' | ' ')) {
+        Some(char) => Err(InvalidModId::Character(char)),
+        None => Ok(())
+    }
 }
 
-#[derive(Deserialize, Debug)]
+/// Entry of the mod list returned by the search
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ModSummary {
+    pub name: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub downloads_count: u64,
+    #[serde(default)]
+    pub latest_release: Option<ModRelease>
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
 pub struct ModReleaseInfoJson {
+    #[serde(default)]
     pub dependencies: Vec<Dependency>,
     pub factorio_version: Version
 }
@@ -146,11 +462,93 @@ impl Display for DependencyType {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Comparison of a dependency's version, `>=` unless written otherwise
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum VersionOperator {
+    Less,
+    LessOrEqual,
+    Equal,
+    #[default]
+    GreaterOrEqual,
+    Greater
+}
+
+impl VersionOperator {
+    pub fn accepts(&self, version: &Version, required: &Version) -> bool {
+        match self {
+            VersionOperator::Less => version < required,
+            VersionOperator::LessOrEqual => version <= required,
+            VersionOperator::Equal => version == required,
+            VersionOperator::GreaterOrEqual => version >= required,
+            VersionOperator::Greater => version > required
+        }
+    }
+}
+
+impl Display for VersionOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VersionOperator::Less => "<",
+            VersionOperator::LessOrEqual => "<=",
+            VersionOperator::Equal => "=",
+            VersionOperator::GreaterOrEqual => ">=",
+            VersionOperator::Greater => ">"
+        })
+    }
+}
+
+/// Allowed versions of a pinned mod like `>=1.0, <2.0`, all comparisons have to hold.
+/// A version without an operator means exactly that version.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct VersionRange(pub Vec<(VersionOperator, Version)>);
+
+impl VersionRange {
+    pub fn accepts(&self, version: &Version) -> bool {
+        self.0.iter().all(|(operator, required)| operator.accepts(version, required))
+    }
+}
+
+impl FromStr for VersionRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut result = Vec::new();
+
+        for part in s.split(',').map(|x| x.trim()) {
+            let (operator, version) = [
+                (">=", VersionOperator::GreaterOrEqual), ("<=", VersionOperator::LessOrEqual),
+                (">", VersionOperator::Greater), ("<", VersionOperator::Less), ("=", VersionOperator::Equal)
+            ].into_iter()
+                .find_map(|(prefix, operator)| part.strip_prefix(prefix).map(|x| (operator, x)))
+                .unwrap_or((VersionOperator::Equal, part));
+
+            let version = version.trim();
+            if version.is_empty() {
+                return Err(format!("\"{}\" has no version", part));
+            }
+
+            result.push((operator, Version::from_str(version).map_err(|_| format!("invalid version \"{}\"", version))?));
+        }
+
+        Ok(VersionRange(result))
+    }
+}
+
+impl Display for VersionRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|(operator, version)| format!("{} {}", operator, version)).collect();
+        f.write_str(&parts.join(", "))
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Dependency {
     pub mod_id: String,
     pub version: Option<Version>,
-    pub dependency_type: DependencyType
+    pub operator: VersionOperator,
+    pub dependency_type: DependencyType,
+    /// `false` for `~` dependencies, which are required but don't affect the load order
+    pub affects_load_order: bool
 }
 
 struct DependencyVisitor;
@@ -184,7 +582,43 @@ impl<'de> Deserialize<'de> for Dependency {
 
 impl Dependency {
     pub fn new(mod_id: String, version: Option<Version>, dependency_type: DependencyType) -> Self {
-        Dependency {mod_id, version, dependency_type}
+        Dependency {mod_id, version, operator: VersionOperator::GreaterOrEqual, dependency_type, affects_load_order: true}
+    }
+
+    /// Whether `version` of the mod meets the requirement, any version does without a required one
+    pub fn accepts(&self, version: &Version) -> bool {
+        match &self.version {
+            Some(required) => self.operator.accepts(version, required),
+            None => true
+        }
+    }
+}
+
+impl Serialize for Dependency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.dependency_type {
+            Conflict => f.write_str("! ")?,
+            Optional => f.write_str("? ")?,
+            Require if !self.affects_load_order => f.write_str("~ ")?,
+            Require => {}
+        }
+
+        f.write_str(&self.mod_id)?;
+
+        if let Some(version) = &self.version {
+            write!(f, " {} {}", self.operator, version)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -195,24 +629,36 @@ impl FromStr for Dependency {
         // I will implement better parser for dependencies.... I think
         let mut clear = s.to_string()
             .replace("(", "")
-            .replace(")", "")
-            .replace("~", "");
+            .replace(")", "");
 
         let dependency_type =
             if      clear.starts_with("!") { Conflict }
             else if clear.starts_with("?") { Optional }
             else                                { Require };
 
+        let affects_load_order = !clear.starts_with("~");
+
         clear = clear.replace("!", "")
-            .replace("?", "");
+            .replace("?", "")
+            .replace("~", "");
 
         let mut mod_id;
         let mut version = None;
-        let parts: Vec<&str> = clear.split(">=").collect();
-
-        if parts.len() == 2 {
-            mod_id = parts[0].to_string();
-            version = Some(Version::from_str(parts[1].trim_start().trim_end())?);
+        let mut operator = VersionOperator::GreaterOrEqual;
+
+        if let Some(index) = clear.find(['<', '>', '=']) {
+            let rest = &clear[index..];
+            let (parsed, length) = match rest.get(..2) {
+                Some(">=") => (VersionOperator::GreaterOrEqual, 2),
+                Some("<=") => (VersionOperator::LessOrEqual, 2),
+                _ if rest.starts_with('>') => (VersionOperator::Greater, 1),
+                _ if rest.starts_with('<') => (VersionOperator::Less, 1),
+                _ => (VersionOperator::Equal, 1)
+            };
+
+            operator = parsed;
+            version = Some(Version::from_str(rest[length..].trim())?);
+            mod_id = clear[..index].to_string();
         } else {
             mod_id = clear;
         }
@@ -222,7 +668,181 @@ impl FromStr for Dependency {
         Ok(Dependency{
             mod_id,
             version,
-            dependency_type
+            operator,
+            dependency_type,
+            affects_load_order
         })
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_requirement_is_minimum_factorio_version() {
+        let dependencies = |values: &[&str]| -> Vec<Dependency> { values.iter().map(|x| Dependency::from_str(x).unwrap()).collect() };
+
+        assert_eq!(minimum_factorio_version(&dependencies(&["foo >= 1.0", "base >= 2.0.5"])), Some(&Version::new(2, 0, 5)));
+        assert_eq!(minimum_factorio_version(&dependencies(&["base"])), None);
+        assert_eq!(minimum_factorio_version(&dependencies(&["base < 2.0"])), None);
+        assert_eq!(minimum_factorio_version(&dependencies(&["? base >= 2.0"])), None);
+    }
+
+    #[test]
+    fn changelog_is_grouped_by_version() {
+        let text = "---------------------------------------------------------------------------------------------------
+Version: 1.1.0
+Date: 2024-05-01
+  Features:
+    - Added a thing
+      which is long.
+  Bugfixes:
+    - Fixed a crash.
+---------------------------------------------------------------------------------------------------
+Version: broken
+  Bugfixes:
+    - Skipped.
+---------------------------------------------------------------------------------------------------
+Version: 1.0.0
+  Info:
+    - Initial release.
+";
+
+        assert_eq!(parse_changelog(text), vec![
+            ChangelogEntry {
+                version: Version::new(1, 1, 0),
+                date: Some("2024-05-01".to_string()),
+                sections: vec![
+                    ("Features".to_string(), vec!["Added a thing which is long.".to_string()]),
+                    ("Bugfixes".to_string(), vec!["Fixed a crash.".to_string()]),
+                ]
+            },
+            ChangelogEntry {
+                version: Version::new(1, 0, 0),
+                date: None,
+                sections: vec![("Info".to_string(), vec!["Initial release.".to_string()])]
+            },
+        ]);
+    }
+
+    #[test]
+    fn range_pin_keeps_releases_of_one_major() {
+        let pin = VersionRange::from_str(">=1.0, <2.0").unwrap();
+        let mut mod_info = ModInfo {
+            releases: ["0.9.0", "1.0.0", "1.4.2", "2.0.0", "2.1.0"].iter()
+                .map(|x| ModRelease { version: Version::from_str(x).unwrap(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+
+        mod_info.retain_pinned(&pin);
+
+        let versions: Vec<String> = mod_info.releases.iter().map(|x| x.version.to_string()).collect();
+        assert_eq!(versions, vec!["1.0.0", "1.4.2"]);
+        assert_eq!(pin.to_string(), ">= 1.0.0, < 2.0.0");
+        assert_eq!(VersionRange::from_str("1.2.3"), Ok(VersionRange(vec![(VersionOperator::Equal, Version::new(1, 2, 3))])));
+        assert!(VersionRange::from_str(">=1.0,").is_err());
+    }
+
+    #[test]
+    fn versions_equal_by_ord_are_one_set_entry() {
+        let versions: Vec<Version> = ["1.0", "1.0.0", "1.0.1", "1.1"].iter().map(|x| Version::from_str(x).unwrap()).collect();
+        let set: std::collections::HashSet<&Version> = versions.iter().collect();
+
+        assert_eq!(versions[0].cmp(&versions[1]), Equal);
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn short_releases_have_no_dependencies() {
+        let mod_info: ModShort = serde_json::from_str(r#"{"name": "foo", "category": "tweaks", "releases": [
+            {"version": "1.0.0", "info_json": {"factorio_version": "1.1"}, "released_at": "2024-01-01T00:00:00Z"},
+            {"version": "bad", "info_json": {"factorio_version": "2.0"}}
+        ]}"#).unwrap();
+
+        assert_eq!(mod_info.releases.len(), 1);
+        assert_eq!(mod_info.releases[0].info_json.factorio_version, Version::new(1, 1, 0));
+    }
+
+    #[test]
+    fn releases_are_sorted_and_deduplicated() {
+        let mut mod_info: ModInfo = serde_json::from_str(r#"{"releases": [
+            {"version": "1.1.0", "info_json": {"factorio_version": "2.0"}, "file_name": "first"},
+            {"version": "not a version", "info_json": {"factorio_version": "2.0"}},
+            {"version": "1.0.0", "info_json": {"factorio_version": "2.0"}},
+            {"version": "1.1.0", "info_json": {"factorio_version": "2.0"}, "file_name": "second"}
+        ]}"#).unwrap();
+
+        mod_info.sort_releases();
+
+        let versions: Vec<String> = mod_info.releases.iter().map(|x| x.version.to_string()).collect();
+        assert_eq!(versions, vec!["1.0.0", "1.1.0"]);
+        assert_eq!(mod_info.releases[1].file_name, "second");
+    }
+
+    #[test]
+    fn no_load_order_dependency_round_trip() {
+        let dependency = Dependency::from_str("~ foo >= 1.0").unwrap();
+
+        assert_eq!(dependency.mod_id, "foo");
+        assert_eq!(dependency.version, Some(Version::new(1, 0, 0)));
+        assert_eq!(dependency.dependency_type, Require);
+        assert!(!dependency.affects_load_order);
+
+        assert_eq!(dependency.to_string(), "~ foo >= 1.0.0");
+        assert_eq!(Dependency::from_str(&dependency.to_string()).unwrap(), dependency);
+    }
+
+    #[test]
+    fn partial_version_compares_specified_components() {
+        let version = Version::new(1, 1, 109);
+
+        assert!(PartialVersion::from_str("1.1").unwrap().matches(&version));
+        assert!(PartialVersion::from_str("1").unwrap().matches(&version));
+        assert!(PartialVersion::from_str("1.1.109").unwrap().matches(&version));
+        assert!(!PartialVersion::from_str("1.1.110").unwrap().matches(&version));
+        assert!(!PartialVersion::from_str("2.0").unwrap().matches(&version));
+
+        assert_eq!(PartialVersion::from_str("1.0").unwrap().cmp_version(&version), Less);
+        assert_eq!(PartialVersion::from_str("2").unwrap().cmp_version(&version), Greater);
+        assert_eq!(PartialVersion::major_minor(&version).to_string(), "1.1");
+    }
+
+    #[test]
+    fn dependency_operators() {
+        let dependency = Dependency::from_str("foo = 1.0").unwrap();
+        assert_eq!(dependency.mod_id, "foo");
+        assert_eq!(dependency.operator, VersionOperator::Equal);
+        assert!(dependency.accepts(&Version::new(1, 0, 0)));
+        assert!(!dependency.accepts(&Version::new(1, 0, 1)));
+
+        let dependency = Dependency::from_str("? bar<2.0").unwrap();
+        assert_eq!(dependency.mod_id, "bar");
+        assert_eq!(dependency.dependency_type, Optional);
+        assert!(dependency.accepts(&Version::new(1, 9, 9)));
+        assert!(!dependency.accepts(&Version::new(2, 0, 0)));
+    }
+
+    #[test]
+    fn mod_id_charset() {
+        assert_eq!(validate_mod_id("Krastorio2"), Ok(()));
+        assert_eq!(validate_mod_id("Squeak Through"), Ok(()));
+        assert_eq!(validate_mod_id("even-distribution_fix"), Ok(()));
+        assert_eq!(validate_mod_id(""), Err(InvalidModId::Empty));
+        assert_eq!(validate_mod_id(" foo"), Err(InvalidModId::Whitespace));
+        assert_eq!(validate_mod_id("foo/bar"), Err(InvalidModId::Character('/')));
+        assert_eq!(validate_mod_id("Space Age: Extra"), Err(InvalidModId::Character(':')));
+        assert_eq!(validate_mod_id(&"a".repeat(101)), Err(InvalidModId::TooLong));
+    }
+
+    #[test]
+    fn dependency_round_trip() {
+        for value in ["foo", "foo >= 1.2.3", "! bar", "? baz >= 0.1.0", "foo = 1.0.0", "foo < 2.0.0", "foo <= 1.1.0", "foo > 0.1.0"] {
+            let dependency = Dependency::from_str(value).unwrap();
+
+            assert!(dependency.affects_load_order);
+            assert_eq!(dependency.to_string(), value);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,123 @@
+use crate::instance::Instance;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+/// Entry of `mod-list.json`, the file where the game stores which mods are enabled
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ModListEntry {
+    pub name: String,
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct ModList {
+    pub mods: Vec<ModListEntry>
+}
+
+/// Changes between two versions of `mod-list.json`
+#[derive(Default, Debug, Eq, PartialEq)]
+pub struct ModListDiff {
+    /// Newly listed mods with whether they are enabled
+    pub added: Vec<(String, bool)>,
+    pub removed: Vec<String>,
+    /// Listed mods whose state changed, with the new state
+    pub toggled: Vec<(String, bool)>
+}
+
+impl ModListDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.toggled.is_empty()
+    }
+}
+
+pub fn mod_list_path(instance: &Instance) -> PathBuf {
+    instance.mods_path.join("mod-list.json")
+}
+
+impl ModList {
+    pub fn load(instance: &Instance) -> io::Result<Self> {
+        Ok(serde_json::from_reader(File::open(mod_list_path(instance))?)?)
+    }
+
+    pub fn save(&self, instance: &Instance) -> io::Result<()> {
+        Ok(serde_json::to_writer_pretty(File::create(mod_list_path(instance))?, self)?)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.mods.iter().any(|x| x.name == name)
+    }
+
+    pub fn add(&mut self, name: &str, enabled: bool) {
+        self.mods.push(ModListEntry {
+            name: name.to_string(),
+            enabled,
+            other: serde_json::Map::new()
+        });
+    }
+
+    /// Enables or disables the mod, listing it if it isn't yet.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        match self.mods.iter_mut().find(|x| x.name == name) {
+            Some(entry) => entry.enabled = enabled,
+            None => self.add(name, enabled)
+        }
+    }
+
+    /// What changes in the file if it's replaced with `new`.
+    pub fn diff(&self, new: &ModList) -> ModListDiff {
+        let mut result = ModListDiff::default();
+
+        for entry in &new.mods {
+            match self.mods.iter().find(|x| x.name == entry.name) {
+                Some(old) if old.enabled != entry.enabled => result.toggled.push((entry.name.clone(), entry.enabled)),
+                Some(_) => {}
+                None => result.added.push((entry.name.clone(), entry.enabled))
+            }
+        }
+
+        result.removed = self.mods.iter()
+            .filter(|x| !new.contains(&x.name))
+            .map(|x| x.name.clone())
+            .collect();
+
+        result
+    }
+
+    /// The game enables mods which aren't listed yet
+    pub fn is_enabled(&self, name: &str) -> bool {
+        match self.mods.iter().find(|x| x.name == name) {
+            Some(entry) => entry.enabled,
+            None => true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_lists_added_removed_and_toggled_mods() {
+        let mut old = ModList::default();
+        old.add("base", true);
+        old.add("foo", true);
+        old.add("bar", false);
+
+        let mut new = old.clone();
+        new.set_enabled("foo", false);
+        new.set_enabled("bar", false);
+        new.set_enabled("baz", true);
+        new.mods.retain(|x| x.name != "base");
+
+        assert_eq!(old.diff(&new), ModListDiff {
+            added: vec![("baz".to_string(), true)],
+            removed: vec!["base".to_string()],
+            toggled: vec![("foo".to_string(), false)]
+        });
+        assert!(new.diff(&new).is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+use crate::downloader::Downloader;
+use crate::factorio_api::ModSource;
+use crate::history;
+use crate::history::{Action, HistoryEntry};
+use crate::instance::Instance;
+use crate::mod_info::{Dependency, DependencyType, Version};
+use crate::utils;
+use crate::utils::{parallel_map, process_dependencies_of, Changes};
+use std::mem::take;
+use std::path::Path;
+
+/// Stage of installing mods, in the order they run
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    Resolve,
+    Download,
+    Update,
+    Prune,
+    Remove
+}
+
+/// Something that happened while planning or applying changes
+pub enum Event<'a> {
+    PhaseStarted(Phase),
+    /// The info of the mod is about to be fetched while resolving dependencies
+    Resolving(&'a str),
+    Started { id: &'a str, version: &'a Version },
+    /// Bytes of the archive downloaded so far, and its size if the server reports it
+    Progress { id: &'a str, downloaded: u64, total: Option<u64> },
+    Done { id: &'a str, version: &'a Version },
+    Failed { id: &'a str, error: &'a (dyn std::error::Error + Send + Sync + 'static) },
+    /// Another version of an installed mod was removed
+    Pruned(&'a Path),
+    /// A conflicting mod was removed
+    Removed(&'a str)
+}
+
+/// Receives the events of `plan` and `apply`, the download events come from several threads at once.
+pub trait Observer: Sync {
+    fn event(&self, event: Event);
+}
+
+/// Ignores all events
+impl Observer for () {
+    fn event(&self, _: Event) {}
+}
+
+pub struct Plan {
+    pub changes: Changes,
+    /// Roots which failed to resolve, none of their dependencies are in `changes`
+    pub failed: Vec<(String, utils::Error)>
+}
+
+/// Resolves the dependencies of the given mod releases and computes the changes of the instance.
+pub fn plan<S: ModSource>(source: &S, instance: &Instance, roots: Vec<(String, Version)>, observer: &dyn Observer) -> Plan {
+    observer.event(Event::PhaseStarted(Phase::Resolve));
+
+    let resolution = process_dependencies_of(source, instance, roots, &mut |id| observer.event(Event::Resolving(id)));
+
+    Plan {
+        changes: Changes::compute(instance, &resolution.dependencies),
+        failed: resolution.failed
+    }
+}
+
+/// Plans installing exactly the given mod releases, their dependencies are neither resolved nor installed.
+pub fn plan_without_dependencies(instance: &Instance, roots: Vec<(String, Version)>) -> Plan {
+    let dependencies: Vec<Dependency> = roots.into_iter()
+        .map(|(id, version)| Dependency::new(id, Some(version), DependencyType::Require))
+        .collect();
+
+    Plan {
+        changes: Changes::compute(instance, &dependencies),
+        failed: Vec::new()
+    }
+}
+
+/// Dependencies of an installed release which differ from the ones its mod source reported
+pub struct Mismatch {
+    pub id: String,
+    pub version: Version,
+    /// Declared by the installed info.json, but not reported by the source
+    pub added: Vec<Dependency>,
+    /// Reported by the source, but not declared by the installed info.json
+    pub missing: Vec<Dependency>
+}
+
+/// Compares the info.json dependencies of the installed releases with the ones the source reported while resolving,
+/// e.g. to catch repacked archives. Releases which aren't installed or known to the source are skipped.
+pub fn reconcile<S: ModSource>(source: &S, instance: &Instance, installed: &[(String, Version)]) -> Vec<Mismatch> {
+    let mut result = Vec::new();
+
+    for (id, version) in installed {
+        let Some(installed_mod) = instance.read_installed_mod(id, version) else { continue };
+        let Ok(mod_info) = source.get_mod(id) else { continue };
+        let Some(release) = mod_info.releases.iter().find(|x| x.version == *version) else { continue };
+
+        let reported = &release.info_json.dependencies;
+        let added: Vec<Dependency> = installed_mod.dependencies.iter().filter(|x| !reported.contains(x)).cloned().collect();
+        let missing: Vec<Dependency> = reported.iter().filter(|x| !installed_mod.dependencies.contains(x)).cloned().collect();
+
+        if !added.is_empty() || !missing.is_empty() {
+            result.push(Mismatch { id: id.clone(), version: version.clone(), added, missing });
+        }
+    }
+
+    result
+}
+
+#[derive(Default)]
+pub struct Applied {
+    /// Ids of the installed, updated and removed mods
+    pub changed: Vec<String>,
+    /// Mods which failed to download, nothing is done after the phase they failed in.
+    /// A mod whose update failed keeps its installed version.
+    pub failed: Vec<(String, Box<dyn std::error::Error + Send + Sync>)>
+}
+
+/// Downloads `mods` using up to `jobs` parallel downloads, results are in the order of `mods`.
+///
+/// With `replacing` the installed version of each mod is removed from it once the new archive is downloaded.
+fn download_all(downloader: &Downloader, mods: &[(String, Version)], jobs: usize, replacing: Option<&Instance>,
+                observer: &dyn Observer) -> Vec<Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+    parallel_map(mods, jobs, |(id, version)| {
+        observer.event(Event::Started { id, version });
+
+        let progress = |downloaded, total| observer.event(Event::Progress { id, downloaded, total });
+        let result = match replacing {
+            Some(instance) => downloader.replace_with_progress(id, version, &progress, &mut || {
+                instance.remove_mod(id);
+                Ok(())
+            }),
+            None => downloader.download_with_progress(id, version, &progress)
+        };
+
+        match &result {
+            Ok(_) => observer.event(Event::Done { id, version }),
+            Err(error) => observer.event(Event::Failed { id, error: error.as_ref() })
+        }
+
+        result
+    })
+}
+
+/// Installs, updates and removes mods of the instance as planned in `changes`, recording them in the history.
+///
+/// With `prune` other versions of the installed mods are removed from the mods directory.
+pub fn apply(instance: &Instance, instance_name: &str, downloader: &Downloader, mut changes: Changes,
+             jobs: usize, prune: bool, observer: &dyn Observer) -> Applied {
+    let mut result = Applied::default();
+    let mut installed = Vec::new();
+
+    observer.event(Event::PhaseStarted(Phase::Download));
+    let installs = take(&mut changes.install);
+    let targets: Vec<(String, Version)> = installs.iter().map(|x| (x.id.clone(), x.version.clone())).collect();
+
+    for (install, outcome) in installs.into_iter().zip(download_all(downloader, &targets, jobs, None, observer)) {
+        match outcome {
+            Ok(_) => {
+                _ = history::record(&HistoryEntry::new(instance_name, Action::Install, &install.id, None, Some(install.version.clone())));
+                installed.push((install.id.clone(), install.version));
+                result.changed.push(install.id);
+            }
+            Err(err) => result.failed.push((install.id, err))
+        }
+    }
+
+    if !result.failed.is_empty() {
+        return result;
+    }
+
+    observer.event(Event::PhaseStarted(Phase::Update));
+    let updates = take(&mut changes.update);
+    let targets: Vec<(String, Version)> = updates.iter().map(|x| (x.id.clone(), x.new_version.clone())).collect();
+    for (update, outcome) in updates.into_iter().zip(download_all(downloader, &targets, jobs, Some(instance), observer)) {
+        match outcome {
+            Ok(_) => {
+                _ = history::record(&HistoryEntry::new(instance_name, Action::Update, &update.id,
+                                                       Some(update.old_version), Some(update.new_version.clone())));
+                installed.push((update.id.clone(), update.new_version));
+                result.changed.push(update.id);
+            }
+            Err(err) => result.failed.push((update.id, err))
+        }
+    }
+
+    if !result.failed.is_empty() {
+        return result;
+    }
+
+    if prune {
+        observer.event(Event::PhaseStarted(Phase::Prune));
+        for (id, version) in &installed {
+            for path in instance.prune_mod(id, version) {
+                observer.event(Event::Pruned(&path));
+            }
+        }
+    }
+
+    observer.event(Event::PhaseStarted(Phase::Remove));
+    for conflict in take(&mut changes.conflicts) {
+        let version = instance.mods().iter().find(|x| x.name == conflict).map(|x| x.version.clone());
+        instance.remove_mod(conflict.as_str());
+
+        _ = history::record(&HistoryEntry::new(instance_name, Action::Remove, &conflict, version, None));
+        observer.event(Event::Removed(&conflict));
+        result.changed.push(conflict);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{fixture_dir, InstalledMod};
+    use crate::utils::{release, FakeModSource, InstallChange, UpdateChange};
+    use std::fs::remove_dir_all;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Observer for Recorder {
+        fn event(&self, event: Event) {
+            let line = match event {
+                Event::PhaseStarted(phase) => format!("{:?}", phase),
+                Event::Resolving(id) => format!("resolving {}", id),
+                _ => return
+            };
+
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    #[test]
+    fn plan_reports_resolved_mods() {
+        let source = FakeModSource::new(vec![("a", vec![release("1.0.0", &[])])]);
+
+        let instance = Instance::fake(PathBuf::new(), vec![]);
+
+        let recorder = Recorder::default();
+        let plan = plan(&source, &instance, vec![("a".to_string(), Version::new(1, 0, 0)), ("b".to_string(), Version::new(1, 0, 0))], &recorder);
+
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["Resolve", "resolving a", "resolving b"]);
+        assert_eq!(plan.changes.install.len(), 1);
+        assert_eq!(plan.failed.len(), 1);
+    }
+
+    #[test]
+    fn reconcile_reports_dependencies_differing_from_the_source() {
+        let dir = fixture_dir("reconcile", &[
+            ("a_1.0.0", "{\"name\": \"a\", \"version\": \"1.0.0\", \"dependencies\": [\"base\", \"b >= 1.0.0\"]}")
+        ]);
+
+        let source = FakeModSource::new(vec![("a", vec![release("1.0.0", &["base", "? c"])])]);
+
+        let instance = Instance::fake(dir.clone(), vec![]);
+
+        let mismatches = reconcile(&source, &instance, &[("a".to_string(), Version::new(1, 0, 0)), ("b".to_string(), Version::new(1, 0, 0))]);
+        _ = remove_dir_all(&dir);
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].added, vec!["b >= 1.0.0".parse().unwrap()]);
+        assert_eq!(mismatches[0].missing, vec!["? c".parse().unwrap()]);
+    }
+
+    #[test]
+    fn failed_update_keeps_the_installed_version() {
+        let dir = fixture_dir("failed-update", &[
+            ("a_1.0.0", "{\"name\": \"a\", \"version\": \"1.0.0\"}")
+        ]);
+
+        let instance = Instance::fake(dir.clone(), vec![
+            InstalledMod { name: "a".to_string(), version: Version::new(1, 0, 0), dependencies: vec![] }
+        ]);
+        // without mirrors every download fails before anything is written
+        let downloader = Downloader::new(&instance).with_source(crate::downloader::Source::Mirrors(vec![]));
+        let changes = Changes {
+            install: vec![],
+            update: vec![UpdateChange { id: "a".to_string(), old_version: Version::new(1, 0, 0), new_version: Version::new(1, 1, 0) }],
+            conflicts: vec![],
+            optional: vec![],
+            held: vec![]
+        };
+
+        let applied = apply(&instance, "test", &downloader, changes, 1, false, &Recorder::default());
+        let kept = dir.join("a_1.0.0").join("info.json").exists();
+        _ = remove_dir_all(&dir);
+
+        assert_eq!(applied.failed.len(), 1);
+        assert!(applied.changed.is_empty());
+        assert!(kept);
+    }
+
+    #[test]
+    fn failed_download_stops_after_the_download_phase() {
+        let dir = fixture_dir("failed-download", &[]);
+        let instance = Instance::fake(dir.clone(), vec![]);
+        let downloader = Downloader::new(&instance).with_source(crate::downloader::Source::Mirrors(vec![]));
+        let changes = Changes {
+            install: vec![InstallChange { id: "a".to_string(), version: Version::new(1, 0, 0) }],
+            update: vec![],
+            conflicts: vec!["b".to_string()],
+            optional: vec![],
+            held: vec![]
+        };
+
+        let recorder = Recorder::default();
+        let applied = apply(&instance, "test", &downloader, changes, 1, false, &recorder);
+        _ = remove_dir_all(&dir);
+
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["Download"]);
+        assert_eq!(applied.failed.len(), 1);
+        assert_eq!(applied.failed[0].0, "a");
+        assert!(applied.changed.is_empty());
+    }
+}
@@ -0,0 +1,22 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Prints a line to stderr in verbose mode
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::trace::is_verbose() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use trace;
@@ -1,10 +1,15 @@
-use crate::factorio_api::FactorioApi;
+use crate::factorio_api::ModSource;
 use crate::mod_info::DependencyType::Require;
-use crate::mod_info::{Dependency, DependencyType, ModInfo, Version};
-use std::collections::HashMap;
+use crate::mod_info::{Dependency, DependencyType, ModInfo, ModRelease, Version, VersionOperator, VersionRange};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::mem::take;
-use crate::instance::Instance;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread::scope;
+use crate::instance::{InstalledMod, Instance};
+use crate::trace::trace;
+use serde::Serialize;
 
 pub fn is_mod_game_content(id: &str) -> bool {
     id == "base" ||
@@ -13,25 +18,222 @@ pub fn is_mod_game_content(id: &str) -> bool {
         id == "space-age"
 }
 
+/// Formats a size in bytes like `12.3 MiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Quotes a CSV field if it contains a comma, a quote or a line break, quotes inside are doubled.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Joins the fields into a line of CSV.
+pub fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields.iter().map(|x| csv_field(x.as_ref())).collect::<Vec<String>>().join(",")
+}
+
+/// Parses a size like `500MiB`, `1.5 GiB`, `200MB` or a number of bytes. Both binary and decimal units are accepted.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    const UNITS: [(&str, u64); 9] = [
+        ("kib", 1 << 10), ("mib", 1 << 20), ("gib", 1 << 30), ("tib", 1 << 40),
+        ("kb", 1000), ("mb", 1000 * 1000), ("gb", 1000 * 1000 * 1000), ("tb", 1000 * 1000 * 1000 * 1000),
+        ("b", 1),
+    ];
+
+    let lowercase = value.trim().to_lowercase();
+    let (number, multiplier) = UNITS.iter()
+        .find_map(|(unit, multiplier)| lowercase.strip_suffix(unit).map(|x| (x.trim(), *multiplier)))
+        .unwrap_or((lowercase.as_str(), 1));
+
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok((number * multiplier as f64) as u64),
+        _ => Err(format!("\"{}\" isn't a size like 500MiB or 2GB", value))
+    }
+}
+
+/// Applies `f` to every item using up to `jobs` threads, results are in the order of `items`.
+pub fn parallel_map<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+
+    scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else { break };
+
+                let result = f(item);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|x| x.0);
+    results.into_iter().map(|x| x.1).collect()
+}
+
+/// Whether `pattern` has wildcards for `glob_match`
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Matches `text` against `pattern`, in which `*` stands for any chars and `?` for a single char.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // the last `*` and the position in the text it's matched up to, for backtracking
+    let mut star = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            star = Some((star_p, star_t + 1));
+            p = star_p + 1;
+            t = star_t + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Installed mods which require `id`, with the requirement. Optional dependencies don't count.
+pub fn required_by<'a>(mods: &'a [InstalledMod], id: &str) -> Vec<(&'a InstalledMod, &'a Dependency)> {
+    mods.iter()
+        .flat_map(|installed| installed.dependencies.iter().map(move |x| (installed, x)))
+        .filter(|(_, x)| x.dependency_type == Require && x.mod_id == id)
+        .collect()
+}
+
+/// Levenshtein distance between two strings, counted in chars
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[derive(Clone)]
 struct ExtendedDependency {
     version: Option<Version>,
     dependency_type: DependencyType,
     usages_count: i64
 }
 
-struct DependenciesProcessor<'a> {
-    need_process: Vec<Dependency>,
+struct DependenciesProcessor<'a, S: ModSource> {
+    /// Dependencies with the id of the mod which requires them
+    need_process: Vec<(Dependency, String)>,
     dependencies: HashMap<String, ExtendedDependency>,
-    factorio_api: &'a FactorioApi<'a>,
+    /// Versioned requirements seen so far with the mods which require them, by the required mod id
+    requirements: HashMap<String, Vec<(Dependency, String)>>,
+    fetched: HashMap<String, ModInfo>,
+    source: &'a S,
     instance: &'a Instance,
+    on_resolve: &'a mut dyn FnMut(&str),
 }
 
-impl<'a> DependenciesProcessor<'a> {
-    fn new(factorio_api: &'a FactorioApi<'a>, instance: &'a Instance) -> Self {
-        DependenciesProcessor { factorio_api, instance, need_process: vec![], dependencies: HashMap::new() }
+impl<'a, S: ModSource> DependenciesProcessor<'a, S> {
+    fn new(source: &'a S, instance: &'a Instance, on_resolve: &'a mut dyn FnMut(&str)) -> Self {
+        DependenciesProcessor { source, instance, on_resolve, need_process: vec![], dependencies: HashMap::new(),
+            requirements: HashMap::new(), fetched: HashMap::new() }
+    }
+
+    fn get_mod(&mut self, id: &String) -> Result<ModInfo, ureq::Error> {
+        if let Some(mod_info) = self.fetched.get(id) {
+            return Ok(mod_info.clone());
+        }
+
+        (self.on_resolve)(id);
+
+        let mod_info = self.source.get_mod(id)?;
+        self.fetched.insert(id.clone(), mod_info.clone());
+
+        if !mod_info.name.is_empty() && &mod_info.name != id {
+            self.fetched.insert(mod_info.name.clone(), mod_info.clone());
+        }
+
+        Ok(mod_info)
+    }
+
+    fn process_pending(&mut self) -> Result<(), Error> {
+        while !self.need_process.is_empty() {
+            for (dependency, required_by) in take(&mut self.need_process) {
+                self.process_dependency(dependency, required_by)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records a versioned requirement, failing if it and an earlier one can't both be met.
+    ///
+    /// Only exact versions are checked: the other requirement has to accept the exact version.
+    fn check_requirement(&mut self, dependency: &Dependency, required_by: &str) -> Result<(), Error> {
+        if dependency.dependency_type != Require || dependency.version.is_none() {
+            return Ok(());
+        }
+
+        let requirements = self.requirements.entry(dependency.mod_id.clone()).or_default();
+
+        for (other, other_required_by) in requirements.iter() {
+            let collides = |exact: &Dependency, other: &Dependency| exact.operator == VersionOperator::Equal &&
+                exact.version.as_ref().is_some_and(|x| !other.accepts(x));
+
+            if collides(dependency, other) || collides(other, dependency) {
+                return Err(Error::VersionConflict {
+                    first: (other.clone(), other_required_by.clone()),
+                    second: (dependency.clone(), required_by.to_string())
+                });
+            }
+        }
+
+        requirements.push((dependency.clone(), required_by.to_string()));
+
+        Ok(())
     }
 
-    fn process_dependency(&mut self, mut dependency: Dependency) -> Result<(), Error> {
+    fn process_dependency(&mut self, mut dependency: Dependency, required_by: String) -> Result<(), Error> {
+        self.check_requirement(&dependency, &required_by)?;
+
         if self.check_satisfied(&dependency) {
             return Ok(());
         }
@@ -43,21 +245,50 @@ impl<'a> DependenciesProcessor<'a> {
                     return Ok(());
                 };
 
-                let mod_info = match self.factorio_api.get_mod(&dependency.mod_id) {
+                let mod_info = match self.get_mod(&dependency.mod_id) {
                     Ok(mod_info) => mod_info,
                     Err(err) => return Err(Error::ModNotFound(dependency.mod_id, err)),
                 };
 
-                let mod_release = match if let Some(version) = &dependency.version {
-                    mod_info.releases.iter().find(|x| &x.version == version )
-                } else {
-                    mod_info.releases.last()
+                // the mod was renamed, it's installed under the new id
+                if !mod_info.name.is_empty() && mod_info.name != dependency.mod_id {
+                    dependency.mod_id = mod_info.name;
+                    self.need_process.push((dependency, required_by));
+                    return Ok(());
+                }
+
+                // `>=` and `=` select the required release itself, the other operators the newest one they accept
+                // which doesn't conflict with the mods planned so far, or the newest one if all of them do
+                let mod_release = match match &dependency.version {
+                    Some(version) if matches!(dependency.operator, VersionOperator::GreaterOrEqual | VersionOperator::Equal) =>
+                        mod_info.releases.iter().find(|x| &x.version == version),
+                    _ => {
+                        let candidates: Vec<&ModRelease> = mod_info.releases.iter().rev().filter(|x| dependency.accepts(&x.version)).collect();
+                        let selected = candidates.iter().find(|x| !self.introduces_conflict(&dependency.mod_id, x)).or(candidates.first()).copied();
+
+                        if let (Some(selected), Some(newest)) = (selected, candidates.first()) {
+                            if selected.version != newest.version {
+                                trace!("{}: skipped newer releases conflicting with planned mods", dependency);
+                            }
+                        }
+
+                        selected
+                    }
                 } {
                     Some(release) => release,
-                    None => return Err(Error::CantFoundSuitableRelease(dependency.mod_id))
+                    None => {
+                        trace!("{}: no suitable release among {} compatible", dependency, mod_info.releases.len());
+                        if let Some(pin) = self.source.pin(&dependency.mod_id) {
+                            return Err(Error::OutsidePin { pin: pin.clone(), dependency, required_by });
+                        }
+                        return Err(Error::CantFoundSuitableRelease(dependency.mod_id))
+                    }
                 };
 
-                self.need_process.append(&mut mod_release.info_json.dependencies.clone());
+                trace!("{}: selected {} of {} compatible releases", dependency, mod_release.version, mod_info.releases.len());
+
+                self.need_process.extend(mod_release.info_json.dependencies.iter()
+                    .map(|x| (x.clone(), dependency.mod_id.clone())));
 
                 dependency.version = Some(mod_release.version.clone());
                 self.add_dependency(dependency, Some(mod_info));
@@ -68,16 +299,35 @@ impl<'a> DependenciesProcessor<'a> {
         Ok(())
     }
 
+    /// Whether `release` of the mod `id` and a mod planned so far conflict, declared by either of them.
+    fn introduces_conflict(&self, id: &str, release: &ModRelease) -> bool {
+        let planned: Vec<(&String, &Version)> = self.dependencies.iter()
+            .filter(|(_, x)| x.dependency_type == Require && x.usages_count > 0)
+            .filter_map(|(planned_id, x)| Some((planned_id, x.version.as_ref()?)))
+            .collect();
+
+        let declared = release.info_json.dependencies.iter()
+            .filter(|x| x.dependency_type == DependencyType::Conflict)
+            .any(|conflict| planned.iter().any(|(planned_id, version)| **planned_id == conflict.mod_id && conflict.accepts(version)));
+
+        declared || planned.iter()
+            .filter_map(|(planned_id, version)| self.fetched.get(*planned_id)?.releases.iter().find(|x| x.version == **version))
+            .flat_map(|x| &x.info_json.dependencies)
+            .any(|x| x.dependency_type == DependencyType::Conflict && x.mod_id == id && x.accepts(&release.version))
+    }
+
+    /// Whether `dependency` is already met, then its mod isn't fetched and no release is selected for it.
+    ///
+    /// An installed version which meets the requirement is kept even if newer releases exist,
+    /// so resolving never plans updates which aren't needed.
     fn check_satisfied(&mut self, dependency: &Dependency) -> bool {
-        if let Some(installed_mod) = self.instance.mods.iter().find(|x| x.name == dependency.mod_id) {
-            if let Some(version) = &dependency.version {
-                if &installed_mod.version >= version {
+        // an installed mod never satisfies a conflict, it has to reach Changes::compute to be removed
+        if dependency.dependency_type != DependencyType::Conflict {
+            if let Some(installed_mod) = self.instance.mods().iter().find(|x| x.name == dependency.mod_id) {
+                if dependency.accepts(&installed_mod.version) {
                     return true;
                 }
             }
-            else {
-                return true;
-            };
         }
 
         if let Some(extended_dependency) = self.dependencies.get_mut(&dependency.mod_id) {
@@ -85,15 +335,14 @@ impl<'a> DependenciesProcessor<'a> {
                 return true;
             }
 
-            let result = match &dependency.version {
-                None => true,
-                Some(dependency_version) => {
-                    if let Some(version) = &extended_dependency.version {
-                        version >= dependency_version
-                    } else {
-                        false
-                    }
-                }
+            // the mod was only wanted as optional so far, now it has to be installed
+            if extended_dependency.dependency_type == DependencyType::Optional {
+                return false;
+            }
+
+            let result = match &extended_dependency.version {
+                Some(version) => dependency.accepts(version),
+                None => dependency.version.is_none()
             };
 
             if result {
@@ -112,7 +361,10 @@ impl<'a> DependenciesProcessor<'a> {
         if let Some(extended_dependency) = self.dependencies.get_mut(&dependency.mod_id) {
             extended_dependency.usages_count += 1;
 
-            if let Some(version) = &extended_dependency.version {
+            if extended_dependency.dependency_type == DependencyType::Optional && dependency.dependency_type == Require {
+                extended_dependency.dependency_type = Require;
+                extended_dependency.version = dependency.version;
+            } else if let Some(version) = &extended_dependency.version {
                 if let Some(dependency_version) = &dependency.version {
                     if dependency_version > version {
                         if let Some(mod_info) = mod_info {
@@ -159,6 +411,10 @@ impl<'a> DependenciesProcessor<'a> {
 pub enum Error {
     ModNotFound(String, ureq::Error),
     CantFoundSuitableRelease(String),
+    /// Two requirements of the same mod, each with the mod requiring it, which no version meets both
+    VersionConflict { first: (Dependency, String), second: (Dependency, String) },
+    /// A requirement with the mod requiring it, which no release within the pin of the required mod meets
+    OutsidePin { dependency: Dependency, required_by: String, pin: VersionRange },
 }
 
 impl Display for Error {
@@ -168,28 +424,298 @@ impl Display for Error {
                 write!(f, "The mod \"{}\" not found (reason: {})", id, err),
             Error::CantFoundSuitableRelease(id) =>
                 write!(f, "Failed to select release for \"{}\" ", id),
+            Error::VersionConflict { first, second } =>
+                write!(f, "\"{}\" required by {} conflicts with \"{}\" required by {}", first.0, first.1, second.0, second.1),
+            Error::OutsidePin { dependency, required_by, pin } =>
+                write!(f, "\"{}\" required by {} is outside of the pin \"{}\"", dependency, required_by, pin),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ModNotFound(_, err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+pub struct Resolution {
+    pub dependencies: Vec<Dependency>,
+    /// Dependencies declared by the selected release of each resolved mod, by the mod id
+    pub edges: HashMap<String, Vec<Dependency>>,
+    /// Roots which failed to resolve, none of their dependencies are included
+    pub failed: Vec<(String, Error)>
+}
+
+/// Resolves the full dependency set of the given mod releases.
+///
+/// `on_resolve` is called with the id of every mod right before its info is fetched from the source.
+/// Each mod is fetched at most once per call.
+/// A root which fails to resolve doesn't affect the others, it's reported in `Resolution::failed`.
+pub fn process_dependencies_of<'a, S: ModSource>(source: &'a S, instance: &'a Instance, roots: Vec<(String, Version)>,
+                                on_resolve: &'a mut dyn FnMut(&str)) -> Resolution {
+    let mut processor = DependenciesProcessor::new(source, instance, on_resolve);
+    let mut failed = Vec::new();
+
+    for (id, version) in roots {
+        let snapshot = (processor.dependencies.clone(), processor.requirements.clone());
+        processor.need_process.push((Dependency::new(id.clone(), Some(version), Require), id.clone()));
+
+        if let Err(err) = processor.process_pending() {
+            processor.need_process.clear();
+            (processor.dependencies, processor.requirements) = snapshot;
+            failed.push((id, err));
+        }
+    }
+
+    let edges = processor.dependencies.iter()
+        .filter(|(_, dependency)| dependency.usages_count > 0)
+        .filter_map(|(id, dependency)| {
+            let version = dependency.version.as_ref()?;
+            let release = processor.fetched.get(id)?.releases.iter().find(|x| &x.version == version)?;
+
+            Some((id.clone(), release.info_json.dependencies.clone()))
+        })
+        .collect();
+
+    Resolution {
+        edges,
+        dependencies: processor.dependencies.into_iter()
+            .filter(|dependency| dependency.1.usages_count > 0)
+            .map(|x| Dependency::new(x.0, x.1.version, x.1.dependency_type))
+            .collect(),
+        failed
+    }
+}
+
+/// `Require` dependencies of the installed mods which are neither installed nor game content
+/// in a suitable version, paired with the id of the requiring mod.
+pub fn unmet_requirements(instance: &Instance) -> Vec<(String, Dependency)> {
+    let mut result = Vec::new();
+
+    for installed_mod in instance.mods() {
+        for dependency in &installed_mod.dependencies {
+            if dependency.dependency_type != Require {
+                continue
+            }
+
+            let version = instance.mods().iter()
+                .find(|x| x.name == dependency.mod_id)
+                .map(|x| &x.version)
+                .or(instance.game_content_versions.get(&dependency.mod_id));
+
+            let satisfied = version.is_some_and(|x| dependency.accepts(x));
+
+            if !satisfied {
+                result.push((installed_mod.name.clone(), dependency.clone()));
+            }
         }
     }
+
+    result
+}
+
+pub struct LoadOrder {
+    pub order: Vec<String>,
+    /// Mods which can't be ordered because they depend on each other
+    pub cycle: Vec<String>,
 }
 
-impl std::error::Error for Error {}
+/// Computes the order in which the game loads the instance's mods.
+///
+/// A mod is loaded after every present mod it depends on, except `~` dependencies and conflicts.
+/// Mods which are ready to be loaded at the same time are ordered alphabetically.
+pub fn compute_load_order(instance: &Instance) -> LoadOrder {
+    let mut nodes: HashSet<&str> = instance.game_content_versions.keys().map(|x| x.as_str()).collect();
+    nodes.extend(instance.mods().iter().map(|x| x.name.as_str()));
+
+    let mut dependencies: HashMap<&str, HashSet<&str>> = nodes.iter().map(|x| (*x, HashSet::new())).collect();
+
+    for id in instance.game_content_versions.keys() {
+        if id != "base" && nodes.contains("base") {
+            dependencies.get_mut(id.as_str()).unwrap().insert("base");
+        }
+    }
+
+    for installed_mod in instance.mods() {
+        let edges = dependencies.get_mut(installed_mod.name.as_str()).unwrap();
+
+        for dependency in &installed_mod.dependencies {
+            if dependency.affects_load_order && dependency.dependency_type != DependencyType::Conflict &&
+                dependency.mod_id != installed_mod.name && nodes.contains(dependency.mod_id.as_str()) {
+                edges.insert(dependency.mod_id.as_str());
+            }
+        }
+    }
+
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    let mut ready = BTreeSet::new();
+
+    for (id, edges) in &dependencies {
+        remaining.insert(*id, edges.len());
+
+        if edges.is_empty() {
+            ready.insert(*id);
+        }
+
+        for edge in edges {
+            dependents.entry(*edge).or_default().push(*id);
+        }
+    }
+
+    let mut order = Vec::new();
 
-pub fn process_dependencies<'a>(factorio_api: &'a FactorioApi<'a>, instance: &'a Instance, id: String, version: Version)
-                        -> Result<Vec<Dependency>, Error> {
-    let mut processor = DependenciesProcessor::new(factorio_api, instance);
+    while let Some(id) = ready.pop_first() {
+        order.push(id.to_string());
 
-    processor.need_process.push(Dependency::new(id, Some(version), Require));
+        for dependent in dependents.get(id).into_iter().flatten() {
+            let count = remaining.get_mut(dependent).unwrap();
+            *count -= 1;
 
-    while processor.need_process.len() != 0 {
-        for dependency in take(&mut processor.need_process) {
-            processor.process_dependency(dependency)?;
+            if *count == 0 {
+                ready.insert(*dependent);
+            }
+        }
+    }
+
+    let mut cycle: Vec<String> = remaining.into_iter()
+        .filter(|x| x.1 != 0)
+        .map(|x| x.0.to_string())
+        .collect();
+    cycle.sort();
+
+    LoadOrder {
+        order,
+        cycle
+    }
+}
+
+/// Difference between the mods of two instances, each list sorted by id
+#[derive(Serialize)]
+pub struct ModsDiff {
+    pub only_first: Vec<(String, Version)>,
+    pub only_second: Vec<(String, Version)>,
+    /// Mods in both with their versions in the first and in the second one
+    pub different: Vec<(String, Version, Version)>
+}
+
+impl ModsDiff {
+    pub fn compute(first: &[InstalledMod], second: &[InstalledMod]) -> Self {
+        let mut only_first = Vec::new();
+        let mut different = Vec::new();
+
+        for installed_mod in first {
+            match second.iter().find(|x| x.name == installed_mod.name) {
+                Some(other) if other.version != installed_mod.version =>
+                    different.push((installed_mod.name.clone(), installed_mod.version.clone(), other.version.clone())),
+                Some(_) => {}
+                None => only_first.push((installed_mod.name.clone(), installed_mod.version.clone()))
+            }
         }
+
+        let mut only_second: Vec<(String, Version)> = second.iter()
+            .filter(|x| !first.iter().any(|installed_mod| installed_mod.name == x.name))
+            .map(|x| (x.name.clone(), x.version.clone()))
+            .collect();
+
+        only_first.sort();
+        only_second.sort();
+        different.sort();
+
+        ModsDiff { only_first, only_second, different }
     }
 
-    Ok(processor.dependencies.into_iter()
-        .filter(|dependency| dependency.1.usages_count > 0)
-        .map(|x| Dependency::new(x.0, x.1.version, x.1.dependency_type))
-        .collect())
+    pub fn is_empty(&self) -> bool {
+        self.only_first.is_empty() && self.only_second.is_empty() && self.different.is_empty()
+    }
+}
+
+/// Graphviz DOT graph of the resolved mods with their versions. Required dependencies are solid edges,
+/// optional ones dashed and conflicts red.
+pub fn dependencies_dot(resolution: &Resolution) -> String {
+    let mut nodes: Vec<&Dependency> = resolution.dependencies.iter().filter(|x| x.dependency_type == Require).collect();
+    nodes.sort_by(|x1, x2| x1.mod_id.cmp(&x2.mod_id));
+
+    let mut result = String::from("digraph dependencies {\n");
+
+    for node in nodes {
+        let label = match &node.version {
+            Some(version) => format!("{} {}", node.mod_id, version),
+            None => node.mod_id.clone()
+        };
+        result += &format!("    \"{}\" [label=\"{}\"];\n", node.mod_id, label);
+    }
+
+    let mut ids: Vec<&String> = resolution.edges.keys().collect();
+    ids.sort();
+
+    for id in ids {
+        for dependency in &resolution.edges[id] {
+            let style = match dependency.dependency_type {
+                Require => "",
+                DependencyType::Optional => " [style=dashed]",
+                DependencyType::Conflict => " [color=red]"
+            };
+            result += &format!("    \"{}\" -> \"{}\"{};\n", id, dependency.mod_id, style);
+        }
+    }
+
+    result += "}\n";
+    result
+}
+
+/// Indented tree of the dependencies of `root`, like `cargo tree`. Optional dependencies are marked with `?`
+/// and conflicts with `!`, a mod whose dependencies were already shown is marked with `(*)`.
+/// `depth` limits how many levels below the root are shown.
+pub fn dependency_tree(resolution: &Resolution, root: &str, depth: Option<usize>) -> Vec<String> {
+    let versions: HashMap<&str, &Version> = resolution.dependencies.iter()
+        .filter(|x| x.dependency_type == Require)
+        .filter_map(|x| Some((x.mod_id.as_str(), x.version.as_ref()?)))
+        .collect();
+
+    let label = |id: &str| match versions.get(id) {
+        Some(version) => format!("{} {}", id, version),
+        None => id.to_string()
+    };
+
+    fn walk(resolution: &Resolution, label: &dyn Fn(&str) -> String, id: &str, prefix: &str, depth: Option<usize>,
+            shown: &mut HashSet<String>, lines: &mut Vec<String>) {
+        let edges = match resolution.edges.get(id) {
+            Some(edges) if depth != Some(0) => edges,
+            _ => return
+        };
+
+        for (index, dependency) in edges.iter().enumerate() {
+            let last = index + 1 == edges.len();
+            let connector = if last { "└── " } else { "├── " };
+            let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+
+            match dependency.dependency_type {
+                DependencyType::Optional => lines.push(format!("{}{}? {}", prefix, connector, label(&dependency.mod_id))),
+                DependencyType::Conflict => lines.push(format!("{}{}! {}", prefix, connector, dependency.mod_id)),
+                Require => {
+                    let has_dependencies = resolution.edges.get(&dependency.mod_id).is_some_and(|x| !x.is_empty());
+
+                    if has_dependencies && !shown.insert(dependency.mod_id.clone()) {
+                        lines.push(format!("{}{}{} (*)", prefix, connector, label(&dependency.mod_id)));
+                        continue
+                    }
+
+                    lines.push(format!("{}{}{}", prefix, connector, label(&dependency.mod_id)));
+                    walk(resolution, label, &dependency.mod_id, &child_prefix, depth.map(|x| x - 1), shown, lines);
+                }
+            }
+        }
+    }
+
+    let mut lines = vec![label(root)];
+    let mut shown = HashSet::from([root.to_string()]);
+    walk(resolution, &label, root, "", depth, &mut shown, &mut lines);
+
+    lines
 }
 
 pub struct InstallChange {
@@ -206,7 +732,11 @@ pub struct UpdateChange {
 pub struct Changes {
     pub install: Vec<InstallChange>,
     pub update: Vec<UpdateChange>,
-    pub conflicts: Vec<String>
+    pub conflicts: Vec<String>,
+    /// Optional dependencies which are neither installed nor going to be
+    pub optional: Vec<String>,
+    /// Updates which are required but won't be performed
+    pub held: Vec<UpdateChange>
 }
 
 impl Changes {
@@ -214,11 +744,12 @@ impl Changes {
         let mut install: Vec<InstallChange> = Vec::new();
         let mut update: Vec<UpdateChange> = Vec::new();
         let mut conflicts: Vec<String> = Vec::new();
+        let mut optional: Vec<String> = Vec::new();
 
         for dependency in dependencies {
             match dependency.dependency_type {
                 DependencyType::Conflict => {
-                    if let Some(_) = instance.mods.iter().find(|x| { x.name == dependency.mod_id }) {
+                    if let Some(_) = instance.mods().iter().find(|x| { x.name == dependency.mod_id }) {
                         conflicts.push(dependency.mod_id.clone());
                     }
                 }
@@ -229,15 +760,20 @@ impl Changes {
 
                     let version = dependency.version.clone().unwrap();
 
-                    if let Some(installed) = instance.mods.iter().find(|x| x.name == dependency.mod_id) {
-                        if version > installed.version {
+                    if let Some(installed) = instance.mods().iter().find(|x| x.name == dependency.mod_id) {
+                        // lower versions come from exact requirements
+                        if version != installed.version {
                             update.push(UpdateChange{id: dependency.mod_id.clone(), old_version: installed.version.clone(), new_version: version});
                         }
                     } else {
                         install.push(InstallChange{ id: dependency.mod_id.clone(), version})
                     }
                 }
-                DependencyType::Optional => {}
+                DependencyType::Optional => {
+                    if !instance.mods().iter().any(|x| x.name == dependency.mod_id) {
+                        optional.push(dependency.mod_id.clone());
+                    }
+                }
             }
         }
 
@@ -245,6 +781,579 @@ impl Changes {
             install,
             update,
             conflicts,
+            optional,
+            held: Vec::new(),
+        }
+    }
+
+    /// Keeps installed mods at their current versions, the skipped updates are moved to `held`.
+    pub fn hold_updates(&mut self) {
+        self.held.append(&mut self.update);
+    }
+
+    /// Same as `hold_updates`, but only for the given mods.
+    pub fn hold_updates_of(&mut self, ids: &[String]) {
+        let (mut held, update): (Vec<UpdateChange>, Vec<UpdateChange>) = take(&mut self.update).into_iter()
+            .partition(|x| ids.contains(&x.id));
+
+        self.update = update;
+        self.held.append(&mut held);
+    }
+
+    /// Neither installs nor updates the given mods, e.g. mods managed by hand. Their dependencies are kept.
+    pub fn skip(&mut self, ids: &[String]) {
+        self.install.retain(|x| !ids.contains(&x.id));
+        self.update.retain(|x| !ids.contains(&x.id));
+    }
+}
+
+/// Mod source serving the given releases without the portal, counting how often each mod is fetched
+#[cfg(test)]
+pub(crate) struct FakeModSource {
+    mods: HashMap<String, ModInfo>,
+    fetches: Mutex<HashMap<String, usize>>,
+    pub(crate) pins: HashMap<String, VersionRange>,
+}
+
+#[cfg(test)]
+impl FakeModSource {
+    pub(crate) fn new(mods: Vec<(&str, Vec<ModRelease>)>) -> Self {
+        FakeModSource {
+            mods: mods.into_iter()
+                .map(|(id, releases)| (id.to_string(), ModInfo { releases, ..Default::default() }))
+                .collect(),
+            fetches: Mutex::new(HashMap::new()),
+            pins: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn fetches(&self, id: &str) -> usize {
+        *self.fetches.lock().unwrap().get(id).unwrap_or(&0)
+    }
+}
+
+#[cfg(test)]
+impl ModSource for FakeModSource {
+    fn get_mod(&self, name: &str) -> Result<ModInfo, ureq::Error> {
+        *self.fetches.lock().unwrap().entry(name.to_string()).or_insert(0) += 1;
+
+        let mut mod_info = self.mods.get(name).cloned().ok_or(ureq::Error::StatusCode(404))?;
+        if let Some(pin) = self.pin(name) {
+            mod_info.retain_pinned(pin);
+        }
+
+        Ok(mod_info)
+    }
+
+    fn pin(&self, name: &str) -> Option<&VersionRange> {
+        self.pins.get(name)
+    }
+}
+
+/// Release for Factorio 2.0 with the given dependencies
+#[cfg(test)]
+pub(crate) fn release(version: &str, dependencies: &[&str]) -> ModRelease {
+    ModRelease {
+        version: version.parse().unwrap(),
+        info_json: crate::mod_info::ModReleaseInfoJson {
+            dependencies: dependencies.iter().map(|x| x.parse().unwrap()).collect(),
+            factorio_version: Version::new(2, 0, 0),
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn instance(installed: &[(&str, &str)]) -> Instance {
+        Instance::fake(PathBuf::new(), installed.iter()
+            .map(|(name, version)| InstalledMod {
+                name: name.to_string(),
+                version: Version::from_str(version).unwrap(),
+                dependencies: vec![],
+            })
+            .collect())
+    }
+
+    fn resolve(source: &FakeModSource, instance: &Instance, id: &str, version: &str) -> Vec<Dependency> {
+        let resolution = process_dependencies_of(source, instance, vec![(id.to_string(), Version::from_str(version).unwrap())],
+                                                 &mut |_| {});
+        assert!(resolution.failed.is_empty());
+
+        resolution.dependencies
+    }
+
+    fn describe(dependencies: &[Dependency]) -> Vec<String> {
+        let mut result: Vec<String> = dependencies.iter()
+            .map(|x| format!("{} {} {}", x.dependency_type, x.mod_id, match &x.version {
+                Some(version) => version.to_string(),
+                None => "*".to_string(),
+            }))
+            .collect();
+
+        result.sort();
+        result
+    }
+
+    fn describe_changes(changes: &Changes) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mut install: Vec<String> = changes.install.iter()
+            .map(|x| format!("{} {}", x.id, x.version))
+            .collect();
+        let mut update: Vec<String> = changes.update.iter()
+            .map(|x| format!("{} {} -> {}", x.id, x.old_version, x.new_version))
+            .collect();
+        let mut conflicts = changes.conflicts.clone();
+
+        install.sort();
+        update.sort();
+        conflicts.sort();
+
+        (install, update, conflicts)
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn range_pin_limits_selected_releases() {
+        let mut source = FakeModSource::new(vec![
+            ("lib", vec![release("0.9.0", &[]), release("1.0.0", &[]), release("1.3.0", &[]), release("2.0.0", &[]), release("2.2.0", &[])]),
+            ("new", vec![release("1.0.0", &["lib >= 2.0.0"])]),
+            ("any", vec![release("1.0.0", &["lib"])]),
+        ]);
+        source.pins.insert("lib".to_string(), VersionRange::from_str(">=1.0, <2.0").unwrap());
+        let instance = instance(&[]);
+
+        assert!(resolve(&source, &instance, "any", "1.0.0").iter()
+            .any(|x| x.mod_id == "lib" && x.version == Some(Version::new(1, 3, 0))));
+
+        let resolution = process_dependencies_of(&source, &instance, vec![("new".to_string(), Version::new(1, 0, 0))], &mut |_| {});
+        assert!(matches!(&resolution.failed[..], [(_, Error::OutsidePin { required_by, .. })] if required_by == "new"));
+    }
+
+    #[test]
+    fn older_release_is_selected_to_avoid_a_conflict() {
+        let source = FakeModSource::new(vec![
+            ("pack", vec![release("1.0.0", &["other", "lib"])]),
+            ("strict", vec![release("1.0.0", &["other", "lib > 1.0.0"])]),
+            ("alone", vec![release("1.0.0", &["lib"])]),
+            ("other", vec![release("1.0.0", &[])]),
+            ("lib", vec![release("1.0.0", &[]), release("2.0.0", &["! other"])]),
+        ]);
+        let instance = instance(&[]);
+        let lib = |x: &Dependency| x.mod_id == "lib";
+
+        assert_eq!(resolve(&source, &instance, "pack", "1.0.0").into_iter().find(lib).unwrap().version, Some(Version::new(1, 0, 0)));
+        assert_eq!(resolve(&source, &instance, "alone", "1.0.0").into_iter().find(lib).unwrap().version, Some(Version::new(2, 0, 0)));
+        // no release avoids the conflict, it's left for Changes::compute to report
+        assert_eq!(resolve(&source, &instance, "strict", "1.0.0").into_iter().find(lib).unwrap().version, Some(Version::new(2, 0, 0)));
+    }
+
+    #[test]
+    fn mods_diff_groups_by_presence_and_version() {
+        let first = instance(&[("a", "1.0.0"), ("shared", "1.0.0"), ("same", "2.0.0")]);
+        let second = instance(&[("b", "1.0.0"), ("shared", "1.1.0"), ("same", "2.0.0")]);
+
+        let diff = ModsDiff::compute(first.mods(), second.mods());
+
+        assert_eq!(diff.only_first, vec![("a".to_string(), Version::new(1, 0, 0))]);
+        assert_eq!(diff.only_second, vec![("b".to_string(), Version::new(1, 0, 0))]);
+        assert_eq!(diff.different, vec![("shared".to_string(), Version::new(1, 0, 0), Version::new(1, 1, 0))]);
+        assert!(!diff.is_empty());
+        assert!(ModsDiff::compute(first.mods(), first.mods()).is_empty());
+    }
+
+    #[test]
+    fn sizes_with_units() {
+        assert_eq!(parse_size("500MiB"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_size("1.5 GiB"), Ok(3 * 512 * 1024 * 1024));
+        assert_eq!(parse_size("200mb"), Ok(200 * 1000 * 1000));
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("10B"), Ok(10));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("-1GiB").is_err());
+    }
+
+    #[test]
+    fn parallel_map_keeps_order() {
+        let items: Vec<usize> = (0..50).collect();
+
+        assert_eq!(parallel_map(&items, 4, |x| x * 2), items.iter().map(|x| x * 2).collect::<Vec<_>>());
+        assert_eq!(parallel_map(&[] as &[usize], 4, |x| *x), vec![]);
+    }
+
+    #[test]
+    fn csv_fields_with_commas_are_quoted() {
+        assert_eq!(csv_row(&["foo", "1.0.0"]), "foo,1.0.0");
+        assert_eq!(csv_row(&["a, b", "say \"hi\""]), "\"a, b\",\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("bob*", "bobplates"));
+        assert!(glob_match("bob*", "bob"));
+        assert!(glob_match("*-lib", "flib-lib"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("space-?ge", "space-age"));
+        assert!(!glob_match("bob*", "angelsbob"));
+        assert!(!glob_match("a*b", "axxbc"));
+        assert!(!glob_match("space-?ge", "space-aage"));
+    }
+
+    #[test]
+    fn edit_distance_counts_single_char_edits() {
+        assert_eq!(edit_distance("krastorio", "krastorio2"), 1);
+        assert_eq!(edit_distance("bobpower", "bobpowre"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[test]
+    fn each_mod_is_fetched_once_per_resolution() {
+        let source = FakeModSource::new(vec![
+            ("top", vec![release("1.0.0", &["a", "b", "? shared"])]),
+            ("a", vec![release("1.0.0", &["shared", "b"])]),
+            ("b", vec![release("1.0.0", &["shared >= 1.0.0"])]),
+            ("shared", vec![release("1.0.0", &[]), release("1.1.0", &[])]),
+        ]);
+        let instance = instance(&[]);
+
+        resolve(&source, &instance, "top", "1.0.0");
+
+        for id in ["top", "a", "b", "shared"] {
+            assert_eq!(source.fetches(id), 1, "{} was fetched again", id);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn base_dependent_mod() {
+        let source = FakeModSource::new(vec![
+            ("simple", vec![release("1.0.0", &["base >= 2.0"])]),
+        ]);
+        let instance = instance(&[]);
+
+        let dependencies = resolve(&source, &instance, "simple", "1.0.0");
+        assert_eq!(describe(&dependencies), strings(&["Require base 2.0.0", "Require simple 1.0.0"]));
+
+        let changes = Changes::compute(&instance, &dependencies);
+        assert_eq!(describe_changes(&changes), (strings(&["simple 1.0.0"]), vec![], vec![]));
+        assert_eq!(source.fetches("base"), 0);
+    }
+
+    #[test]
+    fn diamond_dependency() {
+        let source = FakeModSource::new(vec![
+            ("top", vec![release("1.0.0", &["left", "right"])]),
+            ("left", vec![release("1.0.0", &["bottom >= 1.0.0"])]),
+            ("right", vec![release("1.0.0", &["bottom >= 1.1.0"])]),
+            ("bottom", vec![
+                release("1.0.0", &["base"]),
+                release("1.1.0", &["base"]),
+                release("1.2.0", &["base"]),
+            ]),
+        ]);
+        let instance = instance(&[]);
+
+        let dependencies = resolve(&source, &instance, "top", "1.0.0");
+        assert_eq!(describe(&dependencies), strings(&[
+            "Require base *",
+            "Require bottom 1.1.0",
+            "Require left 1.0.0",
+            "Require right 1.0.0",
+            "Require top 1.0.0",
+        ]));
+
+        let changes = Changes::compute(&instance, &dependencies);
+        assert_eq!(describe_changes(&changes), (
+            strings(&["bottom 1.1.0", "left 1.0.0", "right 1.0.0", "top 1.0.0"]), vec![], vec![]
+        ));
+        assert_eq!(source.fetches("bottom"), 1);
+    }
+
+    #[test]
+    fn conflict_pair() {
+        let source = FakeModSource::new(vec![
+            ("peace", vec![release("1.0.0", &["! rival", "base"])]),
+        ]);
+        let instance = instance(&[("rival", "1.0.0")]);
+
+        let dependencies = resolve(&source, &instance, "peace", "1.0.0");
+        assert_eq!(describe(&dependencies), strings(&["Conflict rival *", "Require base *", "Require peace 1.0.0"]));
+
+        let changes = Changes::compute(&instance, &dependencies);
+        assert_eq!(describe_changes(&changes), (strings(&["peace 1.0.0"]), vec![], strings(&["rival"])));
+        assert_eq!(source.fetches("rival"), 0);
+    }
+
+    #[test]
+    fn version_bump_of_installed_mod() {
+        let source = FakeModSource::new(vec![
+            ("app", vec![release("1.0.0", &["lib >= 2.0.0"])]),
+            ("lib", vec![
+                release("1.0.0", &["base"]),
+                release("2.0.0", &["core >= 1.0.0"]),
+            ]),
+            ("core", vec![release("1.0.0", &["base"])]),
+        ]);
+        let instance = instance(&[("lib", "1.0.0")]);
+
+        let dependencies = resolve(&source, &instance, "app", "1.0.0");
+        assert_eq!(describe(&dependencies), strings(&[
+            "Require app 1.0.0",
+            "Require base *",
+            "Require core 1.0.0",
+            "Require lib 2.0.0",
+        ]));
+
+        let changes = Changes::compute(&instance, &dependencies);
+        assert_eq!(describe_changes(&changes), (
+            strings(&["app 1.0.0", "core 1.0.0"]), strings(&["lib 1.0.0 -> 2.0.0"]), vec![]
+        ));
+    }
+
+    #[test]
+    fn installed_transitive_dependency_is_kept() {
+        let source = FakeModSource::new(vec![
+            ("app", vec![release("1.0.0", &["lib >= 1.0.0", "helper"])]),
+            ("helper", vec![release("1.0.0", &["lib"])]),
+            ("lib", vec![
+                release("1.0.0", &[]),
+                release("1.5.0", &[]),
+                release("2.0.0", &[]),
+            ]),
+        ]);
+        let instance = instance(&[("lib", "1.5.0")]);
+
+        let dependencies = resolve(&source, &instance, "app", "1.0.0");
+        assert_eq!(describe(&dependencies), strings(&["Require app 1.0.0", "Require helper 1.0.0"]));
+
+        let changes = Changes::compute(&instance, &dependencies);
+        assert_eq!(describe_changes(&changes), (strings(&["app 1.0.0", "helper 1.0.0"]), vec![], vec![]));
+        assert_eq!(source.fetches("lib"), 0);
+    }
+
+    #[test]
+    fn colliding_exact_versions_are_reported() {
+        let source = FakeModSource::new(vec![
+            ("top", vec![release("1.0.0", &["a", "b"])]),
+            ("a", vec![release("1.0.0", &["shared = 1.0.0"])]),
+            ("b", vec![release("1.0.0", &["shared = 2.0.0"])]),
+            ("shared", vec![release("1.0.0", &[]), release("2.0.0", &[])]),
+            ("other", vec![release("1.0.0", &["shared = 2.0.0"])]),
+        ]);
+        let instance = instance(&[]);
+
+        let resolution = process_dependencies_of(&source, &instance, vec![
+            ("top".to_string(), Version::new(1, 0, 0)),
+            ("other".to_string(), Version::new(1, 0, 0)),
+        ], &mut |_| {});
+
+        assert_eq!(resolution.failed.len(), 1);
+        let (root, err) = &resolution.failed[0];
+        assert_eq!(root, "top");
+        assert_eq!(err.to_string(), "\"shared = 1.0.0\" required by a conflicts with \"shared = 2.0.0\" required by b");
+
+        assert_eq!(describe(&resolution.dependencies), strings(&["Require other 1.0.0", "Require shared 2.0.0"]));
+    }
+
+    #[test]
+    fn exact_version_downgrades_installed_mod() {
+        let source = FakeModSource::new(vec![
+            ("app", vec![release("1.0.0", &["lib = 1.0.0"])]),
+            ("lib", vec![release("1.0.0", &[]), release("2.0.0", &[])]),
+        ]);
+        let instance = instance(&[("lib", "2.0.0")]);
+
+        let dependencies = resolve(&source, &instance, "app", "1.0.0");
+        let changes = Changes::compute(&instance, &dependencies);
+        assert_eq!(describe_changes(&changes), (strings(&["app 1.0.0"]), strings(&["lib 2.0.0 -> 1.0.0"]), vec![]));
+    }
+
+    #[test]
+    fn dependency_graph_has_styled_edges() {
+        let source = FakeModSource::new(vec![
+            ("app", vec![release("1.0.0", &["lib >= 1.0.0", "? extra", "! rival"])]),
+            ("lib", vec![release("1.0.0", &[])]),
+        ]);
+
+        let resolution = process_dependencies_of(&source, &instance(&[]), vec![("app".to_string(), Version::new(1, 0, 0))], &mut |_| {});
+
+        assert_eq!(dependencies_dot(&resolution), "digraph dependencies {
+    \"app\" [label=\"app 1.0.0\"];
+    \"lib\" [label=\"lib 1.0.0\"];
+    \"app\" -> \"lib\";
+    \"app\" -> \"extra\" [style=dashed];
+    \"app\" -> \"rival\" [color=red];
+}
+");
+    }
+
+    #[test]
+    fn dependency_tree_marks_repeated_subtrees() {
+        let source = FakeModSource::new(vec![
+            ("app", vec![release("1.0.0", &["a", "b", "? extra", "! rival"])]),
+            ("a", vec![release("1.0.0", &["shared"])]),
+            ("b", vec![release("1.0.0", &["shared"])]),
+            ("shared", vec![release("1.0.0", &["lib"])]),
+            ("lib", vec![release("1.0.0", &[])]),
+        ]);
+
+        let resolution = process_dependencies_of(&source, &instance(&[]), vec![("app".to_string(), Version::new(1, 0, 0))], &mut |_| {});
+
+        assert_eq!(dependency_tree(&resolution, "app", None), strings(&[
+            "app 1.0.0",
+            "├── a 1.0.0",
+            "│   └── shared 1.0.0",
+            "│       └── lib 1.0.0",
+            "├── b 1.0.0",
+            "│   └── shared 1.0.0 (*)",
+            "├── ? extra",
+            "└── ! rival",
+        ]));
+
+        assert_eq!(dependency_tree(&resolution, "app", Some(1)), strings(&[
+            "app 1.0.0",
+            "├── a 1.0.0",
+            "├── b 1.0.0",
+            "├── ? extra",
+            "└── ! rival",
+        ]));
+    }
+
+    #[test]
+    fn skipped_root_keeps_its_dependencies() {
+        let source = FakeModSource::new(vec![
+            ("app", vec![release("1.0.0", &["lib >= 1.0.0"])]),
+            ("lib", vec![release("1.0.0", &[]), release("2.0.0", &[])]),
+        ]);
+        let instance = instance(&[("app", "0.9.0")]);
+
+        let dependencies = resolve(&source, &instance, "app", "1.0.0");
+        let mut changes = Changes::compute(&instance, &dependencies);
+        changes.skip(&strings(&["app"]));
+        assert_eq!(describe_changes(&changes), (strings(&["lib 1.0.0"]), vec![], vec![]));
+    }
+
+    #[test]
+    fn version_bump_drops_dependencies_of_replaced_release() {
+        let source = FakeModSource::new(vec![
+            ("top", vec![release("1.0.0", &["a", "b"])]),
+            ("a", vec![release("1.0.0", &["shared >= 1.0.0"])]),
+            ("b", vec![release("1.0.0", &["shared >= 2.0.0"])]),
+            ("shared", vec![
+                release("1.0.0", &["legacy"]),
+                release("2.0.0", &["base"]),
+            ]),
+            ("legacy", vec![release("1.0.0", &[])]),
+        ]);
+        let instance = instance(&[]);
+
+        let dependencies = resolve(&source, &instance, "top", "1.0.0");
+        assert_eq!(describe(&dependencies), strings(&[
+            "Require a 1.0.0",
+            "Require b 1.0.0",
+            "Require base *",
+            "Require shared 2.0.0",
+            "Require top 1.0.0",
+        ]));
+    }
+
+    #[test]
+    fn optional_dependency_is_reported_but_not_installed() {
+        let source = FakeModSource::new(vec![
+            ("main", vec![release("1.0.0", &["? extra", "? shared", "helper"])]),
+            ("helper", vec![release("1.0.0", &["shared"])]),
+            ("shared", vec![release("1.0.0", &[])]),
+        ]);
+        let instance = instance(&[]);
+
+        let dependencies = resolve(&source, &instance, "main", "1.0.0");
+        assert_eq!(describe(&dependencies), strings(&[
+            "Optional extra *",
+            "Require helper 1.0.0",
+            "Require main 1.0.0",
+            "Require shared 1.0.0",
+        ]));
+
+        let changes = Changes::compute(&instance, &dependencies);
+        assert_eq!(describe_changes(&changes), (
+            strings(&["helper 1.0.0", "main 1.0.0", "shared 1.0.0"]), vec![], vec![]
+        ));
+        assert_eq!(changes.optional, strings(&["extra"]));
+        assert_eq!(source.fetches("extra"), 0);
+    }
+
+    #[test]
+    fn missing_mod_fails_resolution() {
+        let source = FakeModSource::new(vec![
+            ("broken", vec![release("1.0.0", &["ghost"])]),
+        ]);
+        let instance = instance(&[]);
+
+        let resolution = process_dependencies_of(&source, &instance, vec![("broken".to_string(), Version::new(1, 0, 0))],
+                                                 &mut |_| {});
+        assert!(resolution.dependencies.is_empty());
+        assert!(matches!(&resolution.failed[..], [(_, Error::ModNotFound(id, _))] if id == "ghost"));
+    }
+
+    #[test]
+    fn renamed_dependency_is_resolved_by_new_id() {
+        let mut source = FakeModSource::new(vec![
+            ("main", vec![release("1.0.0", &["old"])]),
+            ("new", vec![release("2.0.0", &[])]),
+        ]);
+        let renamed = ModInfo { name: "new".to_string(), ..source.mods["new"].clone() };
+        source.mods.insert("old".to_string(), renamed.clone());
+        source.mods.insert("new".to_string(), renamed);
+
+        let instance = instance(&[]);
+        let dependencies = resolve(&source, &instance, "main", "1.0.0");
+
+        assert_eq!(describe(&dependencies), strings(&["Require main 1.0.0", "Require new 2.0.0"]));
+        assert_eq!(source.fetches("new"), 0);
+    }
+
+    #[test]
+    fn unmet_requirements_of_installed_mods() {
+        let mut instance = instance(&[("helper", "1.0.0")]);
+        instance.mods.get_mut().unwrap().push(InstalledMod {
+            name: "main".to_string(),
+            version: Version::new(1, 0, 0),
+            dependencies: ["base >= 2.0", "helper >= 1.1", "? extra", "ghost", "! enemy"].iter()
+                .map(|x| Dependency::from_str(x).unwrap())
+                .collect(),
+        });
+
+        let unmet: Vec<String> = unmet_requirements(&instance).iter()
+            .map(|(name, dependency)| format!("{}: {}", name, dependency))
+            .collect();
+
+        assert_eq!(unmet, strings(&["main: helper >= 1.1.0", "main: ghost"]));
+    }
+
+    #[test]
+    fn failed_root_does_not_affect_others() {
+        let source = FakeModSource::new(vec![
+            ("broken", vec![release("1.0.0", &["shared", "ghost"])]),
+            ("main", vec![release("1.0.0", &["helper"])]),
+            ("helper", vec![release("1.0.0", &[])]),
+            ("shared", vec![release("1.0.0", &[])]),
+        ]);
+        let instance = instance(&[]);
+
+        let roots = vec![
+            ("broken".to_string(), Version::new(1, 0, 0)),
+            ("main".to_string(), Version::new(1, 0, 0)),
+        ];
+        let resolution = process_dependencies_of(&source, &instance, roots, &mut |_| {});
+
+        assert_eq!(describe(&resolution.dependencies), strings(&["Require helper 1.0.0", "Require main 1.0.0"]));
+        assert_eq!(resolution.failed.len(), 1);
+        assert!(matches!(&resolution.failed[0], (root, Error::ModNotFound(id, _)) if root == "broken" && id == "ghost"));
+    }
+}